@@ -0,0 +1,136 @@
+use crate::sim::is_close;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+/// Powers that the player can cast paying spiritual energy
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Ability {
+    PurifyingBurst,
+    Sanctuary,
+    Recall,
+}
+
+pub struct AbilityDef {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub cost: u64,
+    pub cooldown: f32,
+    pub duration: f32,
+    pub radius: f32,
+    /// Karma per second for the burst, speed multiplier for the recall
+    pub power: f32,
+}
+
+impl Ability {
+    pub const ALL: [Ability; 3] = [Ability::PurifyingBurst, Ability::Sanctuary, Ability::Recall];
+
+    pub fn index(&self) -> usize {
+        *self as _
+    }
+
+    pub fn def(&self) -> AbilityDef {
+        match self {
+            Ability::PurifyingBurst => AbilityDef {
+                name: "Purifying Burst",
+                description: "Raises the karma of the souls around the cursor.",
+                cost: 60,
+                cooldown: 15.0,
+                duration: 0.5,
+                radius: 90.0,
+                power: 3.0,
+            },
+            Ability::Sanctuary => AbilityDef {
+                name: "Sanctuary",
+                description: "Souls around the cursor can't be corrupted for a while.",
+                cost: 120,
+                cooldown: 30.0,
+                duration: 10.0,
+                radius: 80.0,
+                power: 0.0,
+            },
+            Ability::Recall => AbilityDef {
+                name: "Recall",
+                description: "All the Luminals rush towards the cursor.",
+                cost: 40,
+                cooldown: 20.0,
+                duration: 2.0,
+                radius: 0.0,
+                power: 1.5,
+            },
+        }
+    }
+}
+
+/// Ability cast that is still affecting the world
+#[derive(Copy, Clone, Debug)]
+pub struct ActiveAbility {
+    pub ability: Ability,
+    pub pos: Vec2,
+    pub timer: f32,
+}
+
+impl ActiveAbility {
+    pub fn contains(&self, pos: Vec2) -> bool {
+        is_close(pos, self.pos, self.ability.def().radius)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Abilities {
+    cooldowns: [f32; Ability::ALL.len()],
+    pub active: Vec<ActiveAbility>,
+}
+
+impl Abilities {
+    pub fn cooldown(&self, ability: Ability) -> f32 {
+        self.cooldowns[ability.index()]
+    }
+
+    pub fn set_cooldown(&mut self, ability: Ability, time: f32) {
+        self.cooldowns[ability.index()] = time.max(0.0);
+    }
+
+    pub fn is_ready(&self, ability: Ability) -> bool {
+        self.cooldown(ability) <= 0.0
+    }
+
+    pub fn cast(&mut self, ability: Ability, pos: Vec2) {
+        let def = ability.def();
+        self.cooldowns[ability.index()] = def.cooldown;
+        self.active.push(ActiveAbility {
+            ability,
+            pos,
+            timer: def.duration,
+        });
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.cooldowns
+            .iter_mut()
+            .for_each(|cd| *cd = (*cd - dt).max(0.0));
+
+        self.active.iter_mut().for_each(|a| a.timer -= dt);
+        self.active.retain(|a| a.timer > 0.0);
+    }
+
+    /// Extra karma per second at the position and if corruption is frozen there
+    pub fn karma_modifiers(&self, pos: Vec2) -> (f32, bool) {
+        self.active
+            .iter()
+            .filter(|a| a.contains(pos))
+            .fold((0.0, false), |(boost, frozen), a| match a.ability {
+                Ability::PurifyingBurst => (boost + a.ability.def().power, frozen),
+                Ability::Sanctuary => (boost, true),
+                Ability::Recall => (boost, frozen),
+            })
+    }
+
+    /// Position where the Luminals are being recalled to
+    pub fn recall_target(&self) -> Option<Vec2> {
+        self.active
+            .iter()
+            .rev()
+            .find(|a| a.ability == Ability::Recall)
+            .map(|a| a.pos)
+    }
+}
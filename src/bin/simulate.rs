@@ -0,0 +1,399 @@
+//! Runs headless games with scripted policies and prints the results,
+//! useful to get some numbers when balancing params and blessings.
+//!
+//! cargo run --release --no-default-features --bin simulate -- --games 100 --difficulty Normal --map meadow --guide densest --buy cheapest --format csv
+//! (use `--map generated-<seed>` to play on a generated map, `--endless` for the endless mode
+//! `--level <id>` to play a campaign level with its own map and difficulty, and
+//! `--daily <day>` for the daily challenge of that day since 1970-01-01, or `--daily today`)
+
+use glam::Vec2;
+use ld56::campaign::{load_level, LevelDef};
+use ld56::config::{load_preset, GameConfig, DEFAULT_PRESET};
+use ld56::daily::{today, Daily};
+use ld56::map::{load_map, MapDef, DEFAULT_MAP};
+use ld56::mapgen::generate;
+use ld56::params::{load_blessings, Blessing};
+use ld56::replay::check_tick_rate;
+use ld56::sim::{is_close, move_towards, Input, Outcome, Simulation};
+use ld56::souls::Soul;
+use serde::Serialize;
+
+const CLUSTER_RADIUS: f32 = 80.0;
+// how often the guide looks for a new target
+const RETARGET_TIME: f32 = 1.0;
+
+#[derive(Copy, Clone)]
+enum GuidePolicy {
+    Idle,
+    Densest,
+}
+
+#[derive(Copy, Clone)]
+enum BuyPolicy {
+    None,
+    Cheapest,
+}
+
+#[derive(Copy, Clone)]
+enum Format {
+    Csv,
+    Json,
+}
+
+struct Config {
+    difficulty: String,
+    map: String,
+    endless: bool,
+    level: Option<String>,
+    daily: Option<u64>,
+    games: usize,
+    seed: u64,
+    tick_rate: f32,
+    max_time: f32,
+    guide: GuidePolicy,
+    buy: BuyPolicy,
+    format: Format,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            difficulty: DEFAULT_PRESET.to_string(),
+            map: DEFAULT_MAP.to_string(),
+            endless: false,
+            level: None,
+            daily: None,
+            games: 10,
+            seed: 0,
+            tick_rate: 60.0,
+            max_time: 60.0 * 20.0,
+            guide: GuidePolicy::Densest,
+            buy: BuyPolicy::Cheapest,
+            format: Format::Csv,
+        }
+    }
+}
+
+struct GameResult {
+    seed: u64,
+    outcome: Option<Outcome>,
+    time: f32,
+    good_progress: f32,
+    peak_energy: u64,
+    total_energy: u64,
+    score: u64,
+    purchases: Vec<Blessing>,
+}
+
+fn main() -> Result<(), String> {
+    load_blessings()?;
+    let config = parse_args()?;
+    let level = config.level.as_deref().map(load_level).transpose()?;
+    let daily = config.daily.map(Daily::new);
+    let (difficulty, map) = match (&level, &daily) {
+        (Some(l), _) => (l.difficulty.clone(), load_map(&l.map)?),
+        (_, Some(d)) => (DEFAULT_PRESET.to_string(), generate(d.seed())),
+        _ => (config.difficulty.clone(), load_map(&config.map)?),
+    };
+    let preset = load_preset(&difficulty)?;
+
+    // the daily challenge starts from the seed of the day, the next games use the seeds after it
+    let base_seed = daily.as_ref().map_or(config.seed, |d| d.seed());
+    let results = (0..config.games)
+        .map(|i| {
+            let seed = base_seed.wrapping_add(i as u64);
+            run_game(&config, &preset, &map, level.as_ref(), daily.as_ref(), seed)
+        })
+        .collect::<Vec<_>>();
+
+    match config.format {
+        Format::Csv => print_csv(&results),
+        Format::Json => print_json(&results),
+    }
+
+    Ok(())
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for '{arg}'"))
+        };
+        match arg.as_str() {
+            "--difficulty" => config.difficulty = value()?,
+            "--map" => config.map = value()?,
+            "--endless" => config.endless = true,
+            "--level" => config.level = Some(value()?),
+            "--daily" => {
+                config.daily = Some(match value()?.as_str() {
+                    "today" => today(),
+                    v => parse(v)?,
+                })
+            }
+            "--games" => config.games = parse(&value()?)?,
+            "--seed" => config.seed = parse(&value()?)?,
+            "--tick-rate" => {
+                config.tick_rate = parse(&value()?)?;
+                check_tick_rate(config.tick_rate)?;
+            }
+            "--max-time" => config.max_time = parse(&value()?)?,
+            "--guide" => {
+                config.guide = match value()?.as_str() {
+                    "idle" => GuidePolicy::Idle,
+                    "densest" => GuidePolicy::Densest,
+                    v => return Err(format!("Invalid guide policy '{v}' (idle, densest)")),
+                }
+            }
+            "--buy" => {
+                config.buy = match value()?.as_str() {
+                    "none" => BuyPolicy::None,
+                    "cheapest" => BuyPolicy::Cheapest,
+                    v => return Err(format!("Invalid buy policy '{v}' (none, cheapest)")),
+                }
+            }
+            "--format" => {
+                config.format = match value()?.as_str() {
+                    "csv" => Format::Csv,
+                    "json" => Format::Json,
+                    v => return Err(format!("Invalid format '{v}' (csv, json)")),
+                }
+            }
+            _ => return Err(format!("Unknown argument '{arg}'")),
+        }
+    }
+
+    Ok(config)
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{value}'"))
+}
+
+fn run_game(
+    config: &Config,
+    preset: &GameConfig,
+    map: &MapDef,
+    level: Option<&LevelDef>,
+    daily: Option<&Daily>,
+    seed: u64,
+) -> GameResult {
+    let dt = 1.0 / config.tick_rate;
+    let mut sim = Simulation::new(seed, preset.clone(), map.clone());
+    if config.endless {
+        sim.start_endless();
+    }
+    if let Some(def) = level {
+        sim.start_level(def.clone());
+    }
+    if let Some(d) = daily {
+        sim.start_daily(d.clone());
+    }
+    sim.init_spawn();
+
+    let mut guide_pos = luminals_center(&sim.souls).unwrap_or(sim.position);
+    let mut target = guide_pos;
+    let mut retarget_timer = 0.0;
+
+    let mut peak_energy = 0;
+    let mut purchases = vec![];
+
+    while sim.outcome.is_none() && sim.play_time < config.max_time {
+        let is_guiding = match config.guide {
+            GuidePolicy::Idle => false,
+            GuidePolicy::Densest => {
+                retarget_timer -= dt;
+                if retarget_timer <= 0.0 {
+                    retarget_timer = RETARGET_TIME;
+                    target = densest_neutral_cluster(&sim.souls).unwrap_or(target);
+
+                    // go back to pick up luminals if we lost all of them
+                    let has_followers = sim.souls.iter().any(|s| s.is_following);
+                    if !has_followers {
+                        guide_pos = luminals_center(&sim.souls).unwrap_or(guide_pos);
+                    }
+                }
+
+                // a bit slower than the souls, so they can keep up with the guide
+                let speed = sim.params.following_speed * 0.9 * dt;
+                if !is_close(guide_pos, target, speed) {
+                    guide_pos = move_towards(guide_pos, target, speed);
+                }
+                true
+            }
+        };
+
+        let unlock = match config.buy {
+            BuyPolicy::None => None,
+            BuyPolicy::Cheapest => cheapest_blessing(&sim),
+        };
+
+        let input = Input {
+            guide_pos,
+            is_guiding,
+            camera_move: Vec2::ZERO,
+            unlock,
+            ..Default::default()
+        };
+
+        let levels_before = unlock.map(|b| sim.blessings.level(&b));
+        sim.update(dt, &input);
+        if let (Some(b), Some(lvl)) = (unlock, levels_before) {
+            if sim.blessings.level(&b) > lvl {
+                purchases.push(b);
+            }
+        }
+
+        peak_energy = peak_energy.max(sim.energy);
+    }
+
+    GameResult {
+        seed,
+        outcome: sim.outcome,
+        time: sim.play_time,
+        good_progress: sim.good_progress,
+        peak_energy,
+        total_energy: sim.total_energy,
+        score: sim.score().total(),
+        purchases,
+    }
+}
+
+fn luminals_center(souls: &[Soul]) -> Option<Vec2> {
+    let (sum, n) = souls
+        .iter()
+        .filter(|s| s.is_good())
+        .fold((Vec2::ZERO, 0), |(sum, n), s| (sum + s.pos, n + 1));
+    (n > 0).then(|| sum / n as f32)
+}
+
+fn densest_neutral_cluster(souls: &[Soul]) -> Option<Vec2> {
+    let neutrals = souls.iter().filter(|s| s.is_neutral()).collect::<Vec<_>>();
+    neutrals
+        .iter()
+        .max_by_key(|s| {
+            neutrals
+                .iter()
+                .filter(|o| is_close(s.pos, o.pos, CLUSTER_RADIUS))
+                .count()
+        })
+        .map(|s| s.pos)
+}
+
+fn cheapest_blessing(sim: &Simulation) -> Option<Blessing> {
+    Blessing::all()
+        .filter(|b| {
+            let lvl = sim.blessings.level(b);
+            lvl < b.levels() && sim.can_unlock(*b) && sim.energy >= sim.blessing_price(*b)
+        })
+        .min_by_key(|b| sim.blessing_price(*b))
+}
+
+fn outcome_name(outcome: Option<Outcome>) -> &'static str {
+    match outcome {
+        Some(Outcome::Win) => "win",
+        Some(Outcome::Defeat) => "defeat",
+        None => "timeout",
+    }
+}
+
+fn purchase_order(purchases: &[Blessing]) -> String {
+    purchases
+        .iter()
+        .map(|b| b.def().id.as_str())
+        .collect::<Vec<_>>()
+        .join(">")
+}
+
+fn win_rate(results: &[GameResult]) -> f32 {
+    let wins = results
+        .iter()
+        .filter(|r| r.outcome == Some(Outcome::Win))
+        .count();
+    wins as f32 / results.len().max(1) as f32
+}
+
+fn avg_time_to_win(results: &[GameResult]) -> Option<f32> {
+    let times = results
+        .iter()
+        .filter(|r| r.outcome == Some(Outcome::Win))
+        .map(|r| r.time)
+        .collect::<Vec<_>>();
+    (!times.is_empty()).then(|| times.iter().sum::<f32>() / times.len() as f32)
+}
+
+fn print_csv(results: &[GameResult]) {
+    println!("seed,outcome,time,good_progress,peak_energy,total_energy,score,purchases");
+    results.iter().for_each(|r| {
+        println!(
+            "{},{},{:.2},{:.3},{},{},{},{}",
+            r.seed,
+            outcome_name(r.outcome),
+            r.time,
+            r.good_progress,
+            r.peak_energy,
+            r.total_energy,
+            r.score,
+            purchase_order(&r.purchases)
+        );
+    });
+
+    // the summary goes to stderr to keep stdout a valid csv
+    eprintln!("games: {}", results.len());
+    eprintln!("win rate: {:.3}", win_rate(results));
+    match avg_time_to_win(results) {
+        Some(t) => eprintln!("avg time to win: {t:.2}s"),
+        None => eprintln!("avg time to win: -"),
+    }
+}
+
+fn print_json(results: &[GameResult]) {
+    #[derive(Serialize)]
+    struct Game<'a> {
+        seed: u64,
+        outcome: &'a str,
+        time: f32,
+        good_progress: f32,
+        peak_energy: u64,
+        total_energy: u64,
+        score: u64,
+        purchases: Vec<&'a str>,
+    }
+
+    #[derive(Serialize)]
+    struct Summary<'a> {
+        games: Vec<Game<'a>>,
+        win_rate: f32,
+        avg_time_to_win: Option<f32>,
+    }
+
+    let games = results
+        .iter()
+        .map(|r| Game {
+            seed: r.seed,
+            outcome: outcome_name(r.outcome),
+            time: r.time,
+            good_progress: r.good_progress,
+            peak_energy: r.peak_energy,
+            total_energy: r.total_energy,
+            score: r.score,
+            purchases: r.purchases.iter().map(|b| b.def().id.as_str()).collect(),
+        })
+        .collect();
+
+    let summary = Summary {
+        games,
+        win_rate: win_rate(results),
+        avg_time_to_win: avg_time_to_win(results),
+    };
+
+    // non finite numbers are written as null, so the output is always valid json
+    match serde_json::to_string(&summary) {
+        Ok(json) => println!("{json}"),
+        Err(e) => eprintln!("Error writing the results: {e}"),
+    }
+}
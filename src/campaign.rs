@@ -0,0 +1,168 @@
+use crate::conditions::ConditionDef;
+use crate::config::{load_presets, DEFAULT_PRESET};
+use crate::map::{check_groups, load_map, SpawnGroup};
+use crate::params::{Blessing, Prerequisite};
+use serde::{Deserialize, Serialize};
+
+const CAMPAIGN_FILE: &str = "assets/campaign.ron";
+/// Levels completed by the player, stored in the user data directory
+/// (or in the local storage of the browser on the web)
+pub const PROGRESS_FILE: &str = "campaign.ron";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LevelDef {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub map: String,
+    #[serde(default = "default_difficulty")]
+    pub difficulty: String,
+    /// Replaces the souls of the map at the beginning
+    #[serde(default)]
+    pub souls: Option<Vec<SpawnGroup>>,
+    /// Blessings that can be unlocked, all of them if it's not set
+    #[serde(default)]
+    pub blessings: Option<Vec<Blessing>>,
+    /// Completes the level
+    pub win: ConditionDef,
+    /// Fails the level, on top of losing all the influence
+    #[serde(default)]
+    pub lose: Option<ConditionDef>,
+    /// The shadows can corrupt the Eternals, that are immune otherwise
+    #[serde(default)]
+    pub vulnerable_eternals: bool,
+}
+
+fn default_difficulty() -> String {
+    DEFAULT_PRESET.to_string()
+}
+
+impl LevelDef {
+    pub fn allows(&self, b: Blessing) -> bool {
+        match &self.blessings {
+            Some(list) => list.contains(&b),
+            None => true,
+        }
+    }
+
+    /// False if the prerequisite needs a blessing that can't be unlocked in this level
+    fn can_meet(&self, p: &Prerequisite) -> bool {
+        match p {
+            Prerequisite::Level(b, lvl) => self.allows(*b) || b.def().initial_level >= *lvl,
+            Prerequisite::AnyOf(list) => list.iter().any(|p| self.can_meet(p)),
+            Prerequisite::AllOf(list) => list.iter().all(|p| self.can_meet(p)),
+        }
+    }
+
+    /// Text of the win condition, for the level select
+    pub fn goal(&self) -> String {
+        self.win.build().description()
+    }
+}
+
+/// Loads the campaign levels, in the order they're played
+pub fn load_levels() -> Result<Vec<LevelDef>, String> {
+    // read it from disk when possible, so designers can tweak it without compiling again
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(s) = std::fs::read_to_string(CAMPAIGN_FILE) {
+        return parse_levels(&s).map_err(|e| format!("Error loading '{CAMPAIGN_FILE}': {e}"));
+    }
+
+    parse_levels(include_str!("../assets/campaign.ron"))
+        .map_err(|e| format!("Error loading '{CAMPAIGN_FILE}': {e}"))
+}
+
+pub fn load_level(id: &str) -> Result<LevelDef, String> {
+    load_levels()?
+        .into_iter()
+        .find(|l| l.id == id)
+        .ok_or_else(|| format!("Unknown level '{id}'"))
+}
+
+pub fn parse_levels(s: &str) -> Result<Vec<LevelDef>, String> {
+    let levels: Vec<LevelDef> = ron::from_str(s).map_err(|e| e.to_string())?;
+    if levels.is_empty() {
+        return Err("At least one level is needed".to_string());
+    }
+
+    let presets = load_presets()?;
+    levels.iter().enumerate().try_for_each(|(i, l)| {
+        if levels[..i].iter().any(|other| other.id == l.id) {
+            return Err(format!("Level '{}' is defined twice", l.id));
+        }
+
+        if !presets.iter().any(|p| p.name == l.difficulty) {
+            return Err(format!(
+                "Level '{}': unknown difficulty preset '{}'",
+                l.id, l.difficulty
+            ));
+        }
+
+        let map = load_map(&l.map).map_err(|e| format!("Level '{}': {e}", l.id))?;
+        if let Some(souls) = &l.souls {
+            check_groups(souls, map.size())
+                .map_err(|e| format!("Level '{}': in 'souls', {e}", l.id))?;
+        }
+
+        // a blessing in the list is useless if the ones it needs are not
+        let blocked = l
+            .blessings
+            .iter()
+            .flatten()
+            .find(|b| !b.require().iter().all(|p| l.can_meet(p)));
+        if let Some(b) = blocked {
+            return Err(format!(
+                "Level '{}': blessing '{}' is allowed but not the ones it requires",
+                l.id,
+                b.def().id
+            ));
+        }
+
+        l.win
+            .validate()
+            .and_then(|_| l.lose.as_ref().map_or(Ok(()), |c| c.validate()))
+            .map_err(|e| format!("Level '{}': {e}", l.id))
+    })?;
+
+    Ok(levels)
+}
+
+/// Levels completed so far, each level is available once the previous one is completed
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CampaignProgress {
+    pub completed: Vec<String>,
+}
+
+impl CampaignProgress {
+    pub fn is_completed(&self, id: &str) -> bool {
+        self.completed.iter().any(|c| c == id)
+    }
+
+    pub fn is_available(&self, levels: &[LevelDef], idx: usize) -> bool {
+        idx == 0
+            || levels
+                .get(idx - 1)
+                .is_some_and(|l| self.is_completed(&l.id))
+    }
+
+    pub fn complete(&mut self, id: &str) {
+        if !self.is_completed(id) {
+            self.completed.push(id.to_string());
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let s = ron::to_string(self).map_err(|e| e.to_string())?;
+        crate::storage::write(PROGRESS_FILE, &s)
+    }
+
+    /// Empty if the player never completed a level
+    pub fn load() -> Result<Self, String> {
+        if !crate::storage::exists(PROGRESS_FILE) {
+            return Ok(Self::default());
+        }
+
+        ron::from_str(&crate::storage::read(PROGRESS_FILE)?).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,594 @@
+use crate::config::check_range;
+use crate::souls::SoulKind;
+use serde::Deserialize;
+use std::slice::Iter;
+
+/// Values of the simulation that the conditions look at, taken after each tick
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Snapshot {
+    pub good_progress: f32,
+    pub bad_progress: f32,
+    pub play_time: f32,
+    pub total_energy: u64,
+    pub conversions: u64,
+    pub neutrals: usize,
+    pub luminals: usize,
+    pub shadows: usize,
+    pub eternals: usize,
+    /// Waves that came so far
+    pub waves: usize,
+    pub corrupted_shrines: usize,
+}
+
+impl Snapshot {
+    pub fn count(&self, kind: SoulKind) -> usize {
+        match kind {
+            SoulKind::Neutral => self.neutrals,
+            SoulKind::Luminal => self.luminals,
+            SoulKind::Shadow => self.shadows,
+            SoulKind::Eternal => self.eternals,
+        }
+    }
+}
+
+/// Rule that ends the game once it's met, modes and levels combine them to define their goals
+pub trait Condition {
+    /// Evaluated after every tick, the conditions with timers expect to be called on each one
+    fn update(&mut self, dt: f32, s: &Snapshot) -> bool;
+
+    /// What the condition asks for, shown as the goal of the game
+    fn description(&self) -> String;
+
+    /// Shown on the end screen once the condition is met
+    fn reason(&self) -> String {
+        self.description()
+    }
+
+    /// Short text with how close the condition is to be met
+    fn progress(&self, _s: &Snapshot) -> Option<String> {
+        None
+    }
+
+    /// Internal state of the condition, to keep it in the save files
+    fn save_state(&self, _out: &mut Vec<f32>) {}
+
+    fn load_state(&mut self, _state: &mut Iter<f32>) {}
+}
+
+pub type WinCondition = Box<dyn Condition>;
+pub type LoseCondition = Box<dyn Condition>;
+
+/// Count followed by the word, with an `s` if there's more than one
+fn count(n: impl Into<u64>, word: &str) -> String {
+    let n = n.into();
+    if n == 1 {
+        format!("{n} {word}")
+    } else {
+        format!("{n} {word}s")
+    }
+}
+
+fn format_time(seconds: f32) -> String {
+    let seconds = seconds.max(0.0) as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Share of Luminal and Eternal souls reached
+pub struct Influence(pub f32);
+
+impl Condition for Influence {
+    fn update(&mut self, _dt: f32, s: &Snapshot) -> bool {
+        s.good_progress >= self.0
+    }
+
+    fn description(&self) -> String {
+        format!("Reach {:.0}% influence", self.0 * 100.0)
+    }
+
+    fn reason(&self) -> String {
+        format!("The light reached {:.0}% of the souls", self.0 * 100.0)
+    }
+
+    fn progress(&self, s: &Snapshot) -> Option<String> {
+        Some(format!(
+            "Influence: {:.0}% / {:.0}%",
+            s.good_progress * 100.0,
+            self.0 * 100.0
+        ))
+    }
+}
+
+/// Share of Shadow souls reached
+pub struct ShadowInfluence(pub f32);
+
+impl Condition for ShadowInfluence {
+    fn update(&mut self, _dt: f32, s: &Snapshot) -> bool {
+        s.bad_progress >= self.0
+    }
+
+    fn description(&self) -> String {
+        format!("Shadows reach {:.0}% of the souls", self.0 * 100.0)
+    }
+
+    fn reason(&self) -> String {
+        format!("The shadows reached {:.0}% of the souls", self.0 * 100.0)
+    }
+}
+
+/// There are no Luminal or Eternal souls left
+pub struct NoInfluence;
+
+impl Condition for NoInfluence {
+    fn update(&mut self, _dt: f32, s: &Snapshot) -> bool {
+        s.good_progress <= 0.0
+    }
+
+    fn description(&self) -> String {
+        "Your influence drops to zero".to_string()
+    }
+
+    fn reason(&self) -> String {
+        "Your influence has faded into darkness".to_string()
+    }
+}
+
+/// Share of Luminal and Eternal souls kept for some seconds in a row
+pub struct HoldInfluence {
+    pub share: f32,
+    pub seconds: f32,
+    held: f32,
+}
+
+impl HoldInfluence {
+    pub fn new(share: f32, seconds: f32) -> Self {
+        Self {
+            share,
+            seconds,
+            held: 0.0,
+        }
+    }
+}
+
+impl Condition for HoldInfluence {
+    fn update(&mut self, dt: f32, s: &Snapshot) -> bool {
+        if s.good_progress >= self.share {
+            self.held += dt;
+        } else {
+            self.held = 0.0;
+        }
+        self.held >= self.seconds
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "Keep your influence above {:.0}% for {}",
+            self.share * 100.0,
+            format_time(self.seconds)
+        )
+    }
+
+    fn reason(&self) -> String {
+        format!(
+            "Your influence stayed above {:.0}% for {}",
+            self.share * 100.0,
+            format_time(self.seconds)
+        )
+    }
+
+    fn progress(&self, _s: &Snapshot) -> Option<String> {
+        Some(format!(
+            "Above {:.0}%: {} / {}",
+            self.share * 100.0,
+            format_time(self.held),
+            format_time(self.seconds)
+        ))
+    }
+
+    fn save_state(&self, out: &mut Vec<f32>) {
+        out.push(self.held);
+    }
+
+    fn load_state(&mut self, state: &mut Iter<f32>) {
+        self.held = state.next().copied().unwrap_or(0.0);
+    }
+}
+
+/// Souls turned Luminal since the beginning
+pub struct Conversions(pub u64);
+
+impl Condition for Conversions {
+    fn update(&mut self, _dt: f32, s: &Snapshot) -> bool {
+        s.conversions >= self.0
+    }
+
+    fn description(&self) -> String {
+        format!("Convert {}", count(self.0, "soul"))
+    }
+
+    fn reason(&self) -> String {
+        format!("You converted {}", count(self.0, "soul"))
+    }
+
+    fn progress(&self, s: &Snapshot) -> Option<String> {
+        Some(format!("Converted: {} / {}", s.conversions, self.0))
+    }
+}
+
+/// Spiritual energy gathered since the beginning
+pub struct Energy(pub u64);
+
+impl Condition for Energy {
+    fn update(&mut self, _dt: f32, s: &Snapshot) -> bool {
+        s.total_energy >= self.0
+    }
+
+    fn description(&self) -> String {
+        format!("Gather {} spiritual energy", self.0)
+    }
+
+    fn reason(&self) -> String {
+        format!("You gathered {} spiritual energy", self.0)
+    }
+
+    fn progress(&self, s: &Snapshot) -> Option<String> {
+        Some(format!("Energy: {} / {}", s.total_energy, self.0))
+    }
+}
+
+/// Seconds played
+pub struct Time(pub f32);
+
+impl Condition for Time {
+    fn update(&mut self, _dt: f32, s: &Snapshot) -> bool {
+        s.play_time >= self.0
+    }
+
+    fn description(&self) -> String {
+        format!("Last {}", format_time(self.0))
+    }
+
+    fn reason(&self) -> String {
+        format!("{} have passed", format_time(self.0))
+    }
+
+    fn progress(&self, s: &Snapshot) -> Option<String> {
+        Some(format!(
+            "Time: {} / {}",
+            format_time(s.play_time),
+            format_time(self.0)
+        ))
+    }
+}
+
+/// Souls of a kind at the same time
+pub struct Souls {
+    pub kind: SoulKind,
+    pub count: usize,
+}
+
+impl Condition for Souls {
+    fn update(&mut self, _dt: f32, s: &Snapshot) -> bool {
+        s.count(self.kind) >= self.count
+    }
+
+    fn description(&self) -> String {
+        let souls = count(self.count as u64, &format!("{:?} soul", self.kind));
+        format!("Have {souls}")
+    }
+
+    fn reason(&self) -> String {
+        let verb = if self.count == 1 { "is" } else { "are" };
+        let souls = count(self.count as u64, &format!("{:?} soul", self.kind));
+        format!("There {verb} {souls}")
+    }
+
+    fn progress(&self, s: &Snapshot) -> Option<String> {
+        Some(format!(
+            "{:?}: {} / {}",
+            self.kind,
+            s.count(self.kind),
+            self.count
+        ))
+    }
+}
+
+/// There are fewer Eternals than the most there have been
+#[derive(Default)]
+pub struct EternalLost {
+    most: usize,
+}
+
+impl Condition for EternalLost {
+    fn update(&mut self, _dt: f32, s: &Snapshot) -> bool {
+        let lost = s.eternals < self.most;
+        self.most = self.most.max(s.eternals);
+        lost
+    }
+
+    fn description(&self) -> String {
+        "An Eternal is lost".to_string()
+    }
+
+    fn reason(&self) -> String {
+        "An Eternal has been lost".to_string()
+    }
+
+    fn save_state(&self, out: &mut Vec<f32>) {
+        out.push(self.most as _);
+    }
+
+    fn load_state(&mut self, state: &mut Iter<f32>) {
+        self.most = state.next().map_or(0, |v| *v as _);
+    }
+}
+
+/// Shrines of the map taken by the shadows at the same time
+pub struct CorruptedShrines(pub usize);
+
+impl Condition for CorruptedShrines {
+    fn update(&mut self, _dt: f32, s: &Snapshot) -> bool {
+        s.corrupted_shrines >= self.0
+    }
+
+    fn description(&self) -> String {
+        let verb = if self.0 == 1 { "is" } else { "are" };
+        format!("{} {verb} corrupted", count(self.0 as u64, "shrine"))
+    }
+
+    fn reason(&self) -> String {
+        format!("The shadows corrupted {}", count(self.0 as u64, "shrine"))
+    }
+}
+
+/// Met when all of the conditions are met on the same tick
+pub struct All(pub Vec<Box<dyn Condition>>);
+
+impl Condition for All {
+    fn update(&mut self, dt: f32, s: &Snapshot) -> bool {
+        // every condition is updated, some of them need it to keep their timers
+        let mut met = true;
+        for c in &mut self.0 {
+            met &= c.update(dt, s);
+        }
+        met
+    }
+
+    fn description(&self) -> String {
+        join(&self.0, |c| Some(c.description()), " and ")
+    }
+
+    fn reason(&self) -> String {
+        join(&self.0, |c| Some(c.reason()), " and ")
+    }
+
+    fn progress(&self, s: &Snapshot) -> Option<String> {
+        Some(join(&self.0, |c| c.progress(s), ", ")).filter(|p| !p.is_empty())
+    }
+
+    fn save_state(&self, out: &mut Vec<f32>) {
+        self.0.iter().for_each(|c| c.save_state(out));
+    }
+
+    fn load_state(&mut self, state: &mut Iter<f32>) {
+        self.0.iter_mut().for_each(|c| c.load_state(state));
+    }
+}
+
+/// Met when any of the conditions is met, it remembers which one to explain it
+pub struct Any {
+    pub list: Vec<Box<dyn Condition>>,
+    met: Option<usize>,
+}
+
+impl Any {
+    pub fn new(list: Vec<Box<dyn Condition>>) -> Self {
+        Self { list, met: None }
+    }
+}
+
+impl Condition for Any {
+    fn update(&mut self, dt: f32, s: &Snapshot) -> bool {
+        self.met = None;
+        self.list.iter_mut().enumerate().for_each(|(i, c)| {
+            if c.update(dt, s) && self.met.is_none() {
+                self.met = Some(i);
+            }
+        });
+        self.met.is_some()
+    }
+
+    fn description(&self) -> String {
+        join(&self.list, |c| Some(c.description()), " or ")
+    }
+
+    fn reason(&self) -> String {
+        match self.met {
+            Some(i) => self.list[i].reason(),
+            None => self.description(),
+        }
+    }
+
+    fn progress(&self, s: &Snapshot) -> Option<String> {
+        Some(join(&self.list, |c| c.progress(s), ", ")).filter(|p| !p.is_empty())
+    }
+
+    fn save_state(&self, out: &mut Vec<f32>) {
+        self.list.iter().for_each(|c| c.save_state(out));
+    }
+
+    fn load_state(&mut self, state: &mut Iter<f32>) {
+        self.list.iter_mut().for_each(|c| c.load_state(state));
+    }
+}
+
+fn join(
+    list: &[Box<dyn Condition>],
+    text: impl Fn(&dyn Condition) -> Option<String>,
+    separator: &str,
+) -> String {
+    list.iter()
+        .filter_map(|c| text(c.as_ref()))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Conditions as they're written in the data files
+#[derive(Clone, Debug, Deserialize)]
+pub enum ConditionDef {
+    Influence(f32),
+    ShadowInfluence(f32),
+    NoInfluence,
+    HoldInfluence { share: f32, seconds: f32 },
+    Convert(u64),
+    Energy(u64),
+    Time(f32),
+    Souls(SoulKind, usize),
+    EternalLost,
+    CorruptedShrines(usize),
+    All(Vec<ConditionDef>),
+    Any(Vec<ConditionDef>),
+}
+
+impl ConditionDef {
+    pub fn build(&self) -> Box<dyn Condition> {
+        match self {
+            ConditionDef::Influence(share) => Box::new(Influence(*share)),
+            ConditionDef::ShadowInfluence(share) => Box::new(ShadowInfluence(*share)),
+            ConditionDef::NoInfluence => Box::new(NoInfluence),
+            ConditionDef::HoldInfluence { share, seconds } => {
+                Box::new(HoldInfluence::new(*share, *seconds))
+            }
+            ConditionDef::Convert(souls) => Box::new(Conversions(*souls)),
+            ConditionDef::Energy(amount) => Box::new(Energy(*amount)),
+            ConditionDef::Time(seconds) => Box::new(Time(*seconds)),
+            ConditionDef::Souls(kind, count) => Box::new(Souls {
+                kind: *kind,
+                count: *count,
+            }),
+            ConditionDef::EternalLost => Box::new(EternalLost::default()),
+            ConditionDef::CorruptedShrines(count) => Box::new(CorruptedShrines(*count)),
+            ConditionDef::All(list) => Box::new(All(list.iter().map(|c| c.build()).collect())),
+            ConditionDef::Any(list) => Box::new(Any::new(list.iter().map(|c| c.build()).collect())),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ConditionDef::Influence(share) => check_range("Influence", *share, 0.01, 1.0),
+            ConditionDef::ShadowInfluence(share) => {
+                check_range("ShadowInfluence", *share, 0.01, 1.0)
+            }
+            ConditionDef::HoldInfluence { share, seconds } => {
+                check_range("HoldInfluence.share", *share, 0.01, 1.0)?;
+                check_range("HoldInfluence.seconds", *seconds, 1.0, 3600.0)
+            }
+            ConditionDef::Time(seconds) => check_range("Time", *seconds, 1.0, 3600.0),
+            ConditionDef::Convert(0) => Err("'Convert' needs at least one soul".to_string()),
+            ConditionDef::Energy(0) => Err("'Energy' needs some energy".to_string()),
+            ConditionDef::Souls(_, 0) => Err("'Souls' needs at least one soul".to_string()),
+            ConditionDef::CorruptedShrines(0) => {
+                Err("'CorruptedShrines' needs at least one shrine".to_string())
+            }
+            ConditionDef::All(list) | ConditionDef::Any(list) => {
+                if list.is_empty() {
+                    return Err("'All' and 'Any' need at least one condition".to_string());
+                }
+                list.iter().try_for_each(|c| c.validate())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(good_progress: f32, play_time: f32) -> Snapshot {
+        Snapshot {
+            good_progress,
+            play_time,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn all_needs_every_condition() {
+        let mut all =
+            ConditionDef::All(vec![ConditionDef::Influence(0.5), ConditionDef::Time(10.0)]).build();
+
+        assert!(!all.update(1.0, &snapshot(0.6, 5.0)));
+        assert!(!all.update(1.0, &snapshot(0.4, 12.0)));
+        assert!(all.update(1.0, &snapshot(0.6, 12.0)));
+        assert_eq!(
+            all.description(),
+            format!(
+                "{} and {}",
+                Influence(0.5).description(),
+                Time(10.0).description()
+            )
+        );
+    }
+
+    #[test]
+    fn all_keeps_the_timers_of_the_conditions_not_met() {
+        let mut all = All(vec![
+            Box::new(Influence(0.9)),
+            Box::new(HoldInfluence::new(0.5, 2.0)),
+        ]);
+
+        // the influence is not met yet, but the hold timer keeps counting
+        assert!(!all.update(1.0, &snapshot(0.6, 0.0)));
+        assert!(!all.update(1.0, &snapshot(0.6, 0.0)));
+        assert!(all.update(1.0, &snapshot(0.95, 0.0)));
+    }
+
+    #[test]
+    fn any_explains_the_condition_met() {
+        let mut any = Any::new(vec![Box::new(Influence(0.5)), Box::new(Time(10.0))]);
+
+        assert!(!any.update(1.0, &snapshot(0.1, 1.0)));
+        assert_eq!(
+            any.reason(),
+            format!(
+                "{} or {}",
+                Influence(0.5).description(),
+                Time(10.0).description()
+            )
+        );
+
+        assert!(any.update(1.0, &snapshot(0.1, 11.0)));
+        assert_eq!(any.reason(), Time(10.0).reason());
+    }
+
+    #[test]
+    fn nested_state_is_saved_and_loaded() {
+        let def = ConditionDef::Any(vec![
+            ConditionDef::HoldInfluence {
+                share: 0.5,
+                seconds: 10.0,
+            },
+            ConditionDef::All(vec![ConditionDef::HoldInfluence {
+                share: 0.2,
+                seconds: 10.0,
+            }]),
+        ]);
+        let mut condition = def.build();
+        condition.update(3.0, &snapshot(0.3, 0.0));
+
+        let mut state = vec![];
+        condition.save_state(&mut state);
+        assert_eq!(state, vec![0.0, 3.0]);
+
+        let mut loaded = def.build();
+        loaded.load_state(&mut state.iter());
+        assert!(loaded.update(7.0, &snapshot(0.3, 0.0)));
+    }
+
+    #[test]
+    fn counts_are_pluralized() {
+        assert_eq!(CorruptedShrines(1).description(), "1 shrine is corrupted");
+        assert_eq!(CorruptedShrines(2).description(), "2 shrines are corrupted");
+        assert_eq!(Conversions(1).description(), "Convert 1 soul");
+    }
+}
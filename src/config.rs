@@ -0,0 +1,208 @@
+use crate::endless::EndlessConfig;
+use crate::params::Params;
+use crate::tempter::TempterConfig;
+use crate::waves::WaveConfig;
+use crate::zones::ZoneConfig;
+use serde::Deserialize;
+
+const PARAMS_FILE: &str = "assets/params.ron";
+pub const DEFAULT_PRESET: &str = "Normal";
+
+/// Game settings for a difficulty preset, the world itself comes from the map
+#[derive(Clone, Debug, Deserialize)]
+pub struct GameConfig {
+    pub name: String,
+    pub camera_speed: f32,
+    pub karma_change_radius: f32,
+    /// Seconds until the first regular wave
+    pub initial_spawn_time: f32,
+    #[serde(default)]
+    pub waves: WaveConfig,
+    pub win_threshold: f32,
+    /// Part of the energy given back when a blessing level is removed
+    #[serde(default = "default_refund_rate")]
+    pub refund_rate: f32,
+    /// Chance of each soul spawned by the waves to be a wanderer
+    #[serde(default)]
+    pub wanderers: f32,
+    #[serde(default = "default_wander_speed")]
+    pub wander_speed: f32,
+    /// Share of souls that a side needs to win in versus mode
+    #[serde(default = "default_versus_threshold")]
+    pub versus_threshold: f32,
+    /// Shadow agent, disabled if it's not set
+    #[serde(default)]
+    pub tempter: Option<TempterConfig>,
+    /// Strength of the shrines and corrupted pools of the map
+    #[serde(default)]
+    pub zones: ZoneConfig,
+    /// Escalation and score of the endless mode
+    #[serde(default)]
+    pub endless: EndlessConfig,
+    // params without blessings and with all the blessings at max level
+    pub start: Params,
+    pub end: Params,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ParamsFile {
+    presets: Vec<GameConfig>,
+}
+
+fn default_refund_rate() -> f32 {
+    0.75
+}
+
+fn default_wander_speed() -> f32 {
+    35.0
+}
+
+fn default_versus_threshold() -> f32 {
+    0.6
+}
+
+/// Loads the difficulty presets from the params file
+pub fn load_presets() -> Result<Vec<GameConfig>, String> {
+    // read it from disk when possible, so designers can tweak it without compiling again
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(s) = std::fs::read_to_string(PARAMS_FILE) {
+        return parse_presets(&s).map_err(|e| format!("Error loading '{PARAMS_FILE}': {e}"));
+    }
+
+    parse_presets(include_str!("../assets/params.ron"))
+        .map_err(|e| format!("Error loading '{PARAMS_FILE}': {e}"))
+}
+
+pub fn load_preset(name: &str) -> Result<GameConfig, String> {
+    load_presets()?
+        .into_iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| format!("Unknown difficulty preset '{name}'"))
+}
+
+pub fn parse_presets(s: &str) -> Result<Vec<GameConfig>, String> {
+    let file: ParamsFile = ron::from_str(s).map_err(|e| e.to_string())?;
+    if file.presets.is_empty() {
+        return Err("At least one preset is needed".to_string());
+    }
+
+    file.presets.iter().try_for_each(|p| {
+        p.validate()
+            .map_err(|e| format!("Preset '{}': {e}", p.name))
+    })?;
+
+    Ok(file.presets)
+}
+
+pub(crate) fn check_range(name: &str, value: f32, min: f32, max: f32) -> Result<(), String> {
+    if value.is_nan() || value < min || value > max {
+        return Err(format!(
+            "'{name}' must be between {min} and {max} (got {value})"
+        ));
+    }
+
+    Ok(())
+}
+
+impl GameConfig {
+    fn validate(&self) -> Result<(), String> {
+        check_range("camera_speed", self.camera_speed, 0.0, 2000.0)?;
+        check_range("karma_change_radius", self.karma_change_radius, 1.0, 500.0)?;
+        check_range("initial_spawn_time", self.initial_spawn_time, 0.1, 600.0)?;
+        check_range("win_threshold", self.win_threshold, 0.01, 1.0)?;
+        check_range("refund_rate", self.refund_rate, 0.0, 1.0)?;
+        check_range("wanderers", self.wanderers, 0.0, 1.0)?;
+        check_range("wander_speed", self.wander_speed, 0.0, 2000.0)?;
+        check_range("versus_threshold", self.versus_threshold, 0.01, 1.0)?;
+
+        if let Some(tempter) = &self.tempter {
+            check_range("tempter.speed", tempter.speed, 0.0, 2000.0)?;
+            check_range("tempter.radius", tempter.radius, 1.0, 1000.0)?;
+            check_range(
+                "tempter.following_speed",
+                tempter.following_speed,
+                0.0,
+                2000.0,
+            )?;
+            check_range("tempter.decision_time", tempter.decision_time, 0.05, 600.0)?;
+        }
+
+        check_range("waves.delay_step", self.waves.delay_step, 0.0, 600.0)?;
+        check_range("waves.min_delay", self.waves.min_delay, 0.1, 600.0)?;
+        check_range("waves.max_count", self.waves.max_count as f32, 1.0, 500.0)?;
+        check_range("waves.announce_time", self.waves.announce_time, 0.0, 60.0)?;
+
+        check_range(
+            "endless.max_count",
+            self.endless.max_count as f32,
+            1.0,
+            1000.0,
+        )?;
+        check_range(
+            "endless.shadow_growth",
+            self.endless.shadow_growth,
+            0.0,
+            100.0,
+        )?;
+        check_range(
+            "endless.time_points",
+            self.endless.time_points,
+            0.0,
+            10000.0,
+        )?;
+
+        check_range("zones.shrine_rate", self.zones.shrine_rate, 0.0, 10.0)?;
+        check_range(
+            "zones.corruption_rate",
+            self.zones.corruption_rate,
+            0.0,
+            10.0,
+        )?;
+        check_range("zones.capture_time", self.zones.capture_time, 0.1, 600.0)?;
+
+        validate_params("start", &self.start)?;
+        validate_params("end", &self.end)?;
+        Ok(())
+    }
+}
+
+fn validate_params(name: &str, params: &Params) -> Result<(), String> {
+    let field = |f: &str| format!("{name}.{f}");
+    check_range(&field("sacred_radius"), params.sacred_radius, 1.0, 1000.0)?;
+    check_range(
+        &field("karma_change_rate"),
+        params.karma_change_rate,
+        0.0,
+        10.0,
+    )?;
+    check_range(
+        &field("karma_expire_rate"),
+        params.karma_expire_rate,
+        0.0,
+        10.0,
+    )?;
+    check_range(&field("energy_time"), params.energy_time, 0.01, 600.0)?;
+    check_range(
+        &field("following_speed"),
+        params.following_speed,
+        1.0,
+        2000.0,
+    )?;
+    check_range(
+        &field("slow_spawn_time"),
+        params.slow_spawn_time,
+        0.0,
+        600.0,
+    )?;
+    check_range(
+        &field("flock_separation"),
+        params.flock_separation,
+        0.0,
+        10.0,
+    )?;
+    check_range(&field("flock_alignment"), params.flock_alignment, 0.0, 10.0)?;
+    check_range(&field("flock_cohesion"), params.flock_cohesion, 0.0, 10.0)?;
+    check_range(&field("flock_seek"), params.flock_seek, 0.0, 10.0)?;
+    check_range(&field("flock_radius"), params.flock_radius, 1.0, 500.0)?;
+    Ok(())
+}
@@ -0,0 +1,277 @@
+use crate::params::Params;
+use crate::rng::Rng;
+use crate::souls::SoulKind;
+use crate::waves::{Edge, Formation, WaveDef, WaveLocation};
+use serde::{Deserialize, Serialize};
+
+/// Best result of each daily challenge, stored in the user data directory
+/// (or in the local storage of the browser on the web)
+pub const DAILY_FILE: &str = "daily.ron";
+
+// seconds that the sacred circle needs to shrink to its minimum
+const SHRINK_TIME: f32 = 600.0;
+const MIN_SHRINK: f32 = 0.5;
+
+/// Twist of the daily challenge on top of the regular rules
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Modifier {
+    FastShadows,
+    ShrinkingCircle,
+    ExpensiveBlessings,
+    ScarceEnergy,
+    RestlessSouls,
+    BigWaves,
+}
+
+impl Modifier {
+    pub const ALL: [Modifier; 6] = [
+        Modifier::FastShadows,
+        Modifier::ShrinkingCircle,
+        Modifier::ExpensiveBlessings,
+        Modifier::ScarceEnergy,
+        Modifier::RestlessSouls,
+        Modifier::BigWaves,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Modifier::FastShadows => "Shadows convert 50% faster",
+            Modifier::ShrinkingCircle => "The sacred circle shrinks over time",
+            Modifier::ExpensiveBlessings => "Blessings cost double",
+            Modifier::ScarceEnergy => "Souls give half the spiritual energy",
+            Modifier::RestlessSouls => "Most of the souls that arrive wander around",
+            Modifier::BigWaves => "Waves grow twice as big",
+        }
+    }
+}
+
+/// Challenge of the day, everybody playing on the same day gets the same one
+#[derive(Clone, Debug)]
+pub struct Daily {
+    /// Days since 1970-01-01
+    pub day: u64,
+    pub modifiers: Vec<Modifier>,
+    /// Waves on top of the ones of the map
+    pub waves: Vec<WaveDef>,
+}
+
+impl Daily {
+    pub fn new(day: u64) -> Self {
+        let mut rng = Rng::new(seed(day) ^ 0xda11);
+
+        let mut pool = Modifier::ALL.to_vec();
+        let count = 2 + rng.index(2);
+        let modifiers = (0..count)
+            .map(|_| pool.remove(rng.index(pool.len())))
+            .collect();
+
+        let waves = (1..=3)
+            .map(|i| {
+                let edge = [Edge::North, Edge::South, Edge::East, Edge::West][rng.index(4)];
+                let formation =
+                    [Formation::Disk, Formation::Line, Formation::Clusters][rng.index(3)];
+                let shadows = rng.range(0.2..0.6);
+                WaveDef {
+                    time: i as f32 * 90.0 + rng.range(-15.0..15.0).round(),
+                    name: Some(format!("Daily surge {i} of 3!")),
+                    count: 8 + i * 4,
+                    kinds: vec![
+                        (SoulKind::Shadow, shadows),
+                        (SoulKind::Neutral, 1.0 - shadows),
+                    ],
+                    location: WaveLocation::Edge(edge),
+                    formation,
+                    wanderers: None,
+                }
+            })
+            .collect();
+
+        Self {
+            day,
+            modifiers,
+            waves,
+        }
+    }
+
+    pub fn has(&self, m: Modifier) -> bool {
+        self.modifiers.contains(&m)
+    }
+
+    pub fn seed(&self) -> u64 {
+        seed(self.day)
+    }
+
+    /// Changes the params after they're calculated from the blessings
+    pub fn apply(&self, params: &mut Params, play_time: f32) {
+        if self.has(Modifier::ScarceEnergy) {
+            params.energy_amount = (params.energy_amount / 2).max(1);
+        }
+
+        if self.has(Modifier::ShrinkingCircle) {
+            let shrink = (1.0 - play_time / SHRINK_TIME).max(MIN_SHRINK);
+            params.sacred_radius *= shrink;
+        }
+    }
+
+    pub fn price_scale(&self) -> u64 {
+        if self.has(Modifier::ExpensiveBlessings) {
+            2
+        } else {
+            1
+        }
+    }
+
+    pub fn shadow_scale(&self) -> f32 {
+        if self.has(Modifier::FastShadows) {
+            1.5
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Seed of the day, used for the map, the modifiers and the game itself
+pub fn seed(day: u64) -> u64 {
+    // splitmix64, consecutive days give very different seeds
+    let mut z = day.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    // small enough to be shown as the name of the generated map
+    (z ^ (z >> 31)) % 1_000_000
+}
+
+/// Current day in UTC, from the clock of the system so it works offline
+#[cfg(not(target_arch = "wasm32"))]
+pub fn today() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86400)
+}
+
+/// Current day in UTC, from the clock of the browser
+#[cfg(target_arch = "wasm32")]
+pub fn today() -> u64 {
+    (js_sys::Date::now() / 86_400_000.0) as u64
+}
+
+/// Day formatted as YYYY-MM-DD
+pub fn date(day: u64) -> String {
+    // days to civil date, from http://howardhinnant.github.io/date_algorithms.html
+    let z = day as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    format!("{y:04}-{m:02}-{d:02}")
+}
+
+/// How a daily run ended, winning is better than losing, then faster wins or higher scores
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct DailyResult {
+    pub won: bool,
+    pub time: f32,
+    pub score: u64,
+}
+
+impl DailyResult {
+    pub fn is_better_than(&self, other: &DailyResult) -> bool {
+        match (self.won, other.won) {
+            (true, false) => true,
+            (false, true) => false,
+            (true, true) => self.time < other.time,
+            (false, false) => self.score > other.score,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let seconds = self.time as u32;
+        if self.won {
+            format!("won in {}:{:02}", seconds / 60, seconds % 60)
+        } else {
+            format!("{} points", self.score)
+        }
+    }
+}
+
+/// Best result of each day played
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DailyRecords {
+    pub best: Vec<(u64, DailyResult)>,
+}
+
+impl DailyRecords {
+    pub fn best(&self, day: u64) -> Option<DailyResult> {
+        self.best.iter().find(|(d, _)| *d == day).map(|(_, r)| *r)
+    }
+
+    /// Returns true if it's the new best of the day
+    pub fn record(&mut self, day: u64, result: DailyResult) -> bool {
+        match self.best.iter_mut().find(|(d, _)| *d == day) {
+            Some((_, best)) if result.is_better_than(best) => *best = result,
+            Some(_) => return false,
+            None => self.best.push((day, result)),
+        }
+        true
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let s = ron::to_string(self).map_err(|e| e.to_string())?;
+        crate::storage::write(DAILY_FILE, &s)
+    }
+
+    /// Empty if the player never played a daily challenge
+    pub fn load() -> Result<Self, String> {
+        if !crate::storage::exists(DAILY_FILE) {
+            return Ok(Self::default());
+        }
+
+        ron::from_str(&crate::storage::read(DAILY_FILE)?).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_are_formatted_as_dates() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(59), "1970-03-01");
+        assert_eq!(date(11016), "2000-02-29");
+        assert_eq!(date(20088), "2024-12-31");
+    }
+
+    #[test]
+    fn every_day_has_its_own_seed() {
+        let seeds = (20000..20100).map(seed).collect::<Vec<_>>();
+        assert!(seeds.iter().all(|s| *s < 1_000_000));
+        assert!(seeds.windows(2).all(|w| w[0] != w[1]));
+        assert_eq!(seeds[0], seed(20000));
+    }
+
+    #[test]
+    fn same_day_same_challenge() {
+        let (a, b) = (Daily::new(20000), Daily::new(20000));
+        assert_eq!(a.modifiers, b.modifiers);
+        assert_eq!(format!("{:?}", a.waves), format!("{:?}", b.waves));
+        assert!((2..=3).contains(&a.modifiers.len()));
+    }
+
+    #[test]
+    fn only_better_results_are_recorded() {
+        let mut records = DailyRecords::default();
+        let result = |won, time, score| DailyResult { won, time, score };
+
+        assert!(records.record(1, result(false, 300.0, 50)));
+        assert!(!records.record(1, result(false, 300.0, 40)));
+        assert!(records.record(1, result(true, 500.0, 0)));
+        assert!(records.record(1, result(true, 400.0, 0)));
+        assert!(!records.record(1, result(false, 100.0, 900)));
+        assert_eq!(records.best(1).map(|r| r.time), Some(400.0));
+        assert!(records.best(2).is_none());
+    }
+}
@@ -0,0 +1,52 @@
+use serde::Deserialize;
+
+/// How the endless mode gets harder and how its score is counted,
+/// each difficulty preset has its own
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct EndlessConfig {
+    /// Souls of the biggest regular wave, way above the cap of the normal games
+    pub max_count: usize,
+    /// Weight of the Shadows in the regular waves for each minute played, the Neutrals weight 1
+    pub shadow_growth: f32,
+    /// Points for each second survived
+    pub time_points: f32,
+    /// Points for each soul turned Luminal
+    pub conversion_points: u64,
+    /// Points for each unit of spiritual energy gathered
+    pub energy_points: u64,
+}
+
+impl Default for EndlessConfig {
+    fn default() -> Self {
+        Self {
+            max_count: 200,
+            shadow_growth: 0.1,
+            time_points: 10.0,
+            conversion_points: 25,
+            energy_points: 1,
+        }
+    }
+}
+
+/// Points of an endless run
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Score {
+    pub time: u64,
+    pub conversions: u64,
+    pub energy: u64,
+}
+
+impl Score {
+    pub fn new(config: &EndlessConfig, play_time: f32, conversions: u64, energy: u64) -> Self {
+        Self {
+            time: (play_time * config.time_points) as u64,
+            conversions: conversions * config.conversion_points,
+            energy: energy * config.energy_points,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.time + self.conversions + self.energy
+    }
+}
@@ -0,0 +1,20 @@
+pub mod abilities;
+pub mod campaign;
+pub mod conditions;
+pub mod config;
+pub mod daily;
+pub mod endless;
+pub mod map;
+pub mod mapgen;
+pub mod params;
+pub mod replay;
+pub mod rng;
+pub mod save;
+pub mod sim;
+pub mod souls;
+pub mod storage;
+pub mod tempter;
+pub mod terrain;
+pub mod versus;
+pub mod waves;
+pub mod zones;
@@ -1,11 +1,18 @@
-mod params;
 mod resources;
-mod souls;
 mod state;
 
-use crate::params::Blessing;
-use crate::souls::{KarmaConversion, SoulKind};
-use crate::state::{is_close, move_towards, Mode, State, MAP_SIZE, RESOLUTION};
+use crate::state::{Mode, State, RESOLUTION};
+use ld56::abilities::Ability;
+use ld56::daily;
+use ld56::map::ZoneKind;
+use ld56::params::{Blessing, Prerequisite};
+#[cfg(not(target_arch = "wasm32"))]
+use ld56::replay::{Replay, LAST_REPLAY_FILE};
+#[cfg(not(target_arch = "wasm32"))]
+use ld56::save::SAVE_FILE;
+use ld56::sim::{is_close, move_towards};
+use ld56::souls::{KarmaConversion, SoulKind};
+use ld56::terrain::ObstacleKind;
 use rkit::app::{window_height, window_size, window_width, WindowConfig};
 use rkit::draw::{create_draw_2d, Draw2D, Transform2D};
 use rkit::gfx::Color;
@@ -15,12 +22,16 @@ use rkit::input::{
 use rkit::math::{uvec2, vec2, Rect, Vec2};
 use rkit::{gfx, time};
 use std::thread::spawn;
-use strum::IntoEnumIterator;
 
 const LUMINAL_COLOR: Color = Color::rgb(0.171, 0.863, 0.929);
 const SHADOW_COLOR: Color = Color::rgb(0.4325, 0.0489, 0.0872);
 const ETERNAL_COLOR: Color = Color::rgb(1.0, 0.596, 0.171);
 const NEUTRAL_COLOR: Color = Color::WHITE;
+const ROCK_COLOR: Color = Color::rgb(0.35, 0.33, 0.3);
+const RIVER_COLOR: Color = Color::rgb(0.14, 0.29, 0.52);
+const WALL_COLOR: Color = Color::rgb(0.2, 0.2, 0.22);
+// seconds that the arrival of a soul is highlighted
+const ARRIVAL_TIME: f32 = 0.8;
 
 fn main() -> Result<(), String> {
     let win = WindowConfig {
@@ -40,13 +51,6 @@ fn setup() -> State {
     State::new().unwrap()
 }
 
-fn init_spawn(state: &mut State) {
-    state.spawn_souls(30, None);
-    state.spawn_souls(50, Some(SoulKind::Neutral));
-    state.spawn_souls(8, Some(SoulKind::Luminal));
-    state.spawn_souls(8, Some(SoulKind::Shadow));
-}
-
 fn update(state: &mut State) {
     state.update();
 
@@ -75,31 +79,151 @@ fn update(state: &mut State) {
         return;
     }
 
+    if matches!(state.mode, Mode::LevelSelect) {
+        gfx::render_to_frame(&draw).unwrap();
+        draw_level_select(state);
+        return;
+    }
+
     //draw bounds
-    draw.rect(Vec2::ZERO, MAP_SIZE)
+    draw.rect(Vec2::ZERO, state.sim.terrain.map_size)
         .stroke_color(Color::GRAY.with_alpha(0.5))
         .stroke(4.0);
 
-    let alpha = if state.is_guiding { 0.3 } else { 0.04 };
-    let circle_size = state.params.sacred_radius.floor() * 2.0;
+    // obstacles
+    state.sim.terrain.obstacles.iter().for_each(|o| {
+        let color = match o.kind {
+            ObstacleKind::Rock => ROCK_COLOR,
+            ObstacleKind::River => RIVER_COLOR,
+            ObstacleKind::Wall => WALL_COLOR,
+        };
+
+        draw.rect(o.min(), o.max() - o.min())
+            .fill_color(color)
+            .fill()
+            .stroke_color(Color::BLACK.with_alpha(0.5))
+            .stroke(2.0);
+    });
+
+    // zones, the shrines show how close they are to being corrupted
+    state.sim.zones.list.iter().for_each(|z| {
+        let color = match z.kind {
+            ZoneKind::Shrine => lerp_color(LUMINAL_COLOR, SHADOW_COLOR, z.corruption),
+            ZoneKind::CorruptedPool => SHADOW_COLOR,
+            ZoneKind::Sanctuary => NEUTRAL_COLOR,
+        };
+
+        draw.circle(z.radius)
+            .position(z.pos - z.radius)
+            .alpha(0.2)
+            .fill_color(color)
+            .fill();
+
+        if z.kind == ZoneKind::Shrine && z.corruption > 0.0 && !z.is_corrupted {
+            let radius = z.radius * z.corruption;
+            draw.circle(radius)
+                .position(z.pos - radius)
+                .alpha(0.2)
+                .fill_color(SHADOW_COLOR)
+                .fill();
+        }
+    });
+
+    // portals, the color shows the kind of souls that they like to bring
+    state.sim.map.spawn_points.iter().for_each(|p| {
+        let bias = p.kinds.iter().max_by(|(_, a), (_, b)| a.total_cmp(b));
+        let color = match bias {
+            Some((SoulKind::Shadow, _)) => SHADOW_COLOR,
+            Some((SoulKind::Luminal | SoulKind::Eternal, _)) => LUMINAL_COLOR,
+            _ => ETERNAL_COLOR,
+        };
+
+        let radius = p.radius * (0.9 + 0.1 * (elapsed * 2.0).sin());
+        draw.circle(radius)
+            .position(p.pos() - radius)
+            .alpha(0.1)
+            .fill_color(color)
+            .fill();
+    });
+
+    // souls coming out of a portal
+    state.arrivals.iter_mut().for_each(|(pos, t)| {
+        *t += dt;
+        let progress = (*t / ARRIVAL_TIME).min(1.0);
+        let radius = 4.0 + 20.0 * progress;
+        draw.circle(radius)
+            .position(*pos + 8.0 - radius)
+            .alpha(0.5 * (1.0 - progress))
+            .fill_color(ETERNAL_COLOR)
+            .fill();
+    });
+    state.arrivals.retain(|(_, t)| *t < ARRIVAL_TIME);
+
+    let alpha = if state.sim.is_guiding { 0.3 } else { 0.04 };
+    let circle_size = state.sim.params.sacred_radius.floor() * 2.0;
     let elapsed_time = elapsed.sin().abs();
     let min_size = circle_size * 0.8;
     let animated_size = min_size + (circle_size - min_size) * elapsed_time;
 
-    draw.circle(state.params.sacred_radius)
+    // shadow agent
+    if let Some(t) = &state.sim.tempter {
+        let radius = t.config.radius;
+        let size = radius * 2.0 * (0.9 + 0.1 * elapsed_time);
+        draw.image(&state.res.circle)
+            .alpha(0.3)
+            .color(SHADOW_COLOR)
+            .size(Vec2::splat(size))
+            .anchor(Vec2::splat(0.5))
+            .translate(t.interpolated_pos(state.alpha));
+    }
+
+    // second player
+    if let Some(r) = &state.sim.rival {
+        let radius = r.params.sacred_radius;
+        let alpha = if r.is_guiding { 0.4 } else { 0.1 };
+        draw.image(&state.res.circle)
+            .alpha(alpha)
+            .color(SHADOW_COLOR)
+            .size(Vec2::splat(radius * 2.0))
+            .anchor(Vec2::splat(0.5))
+            .translate(r.interpolated_pos(state.alpha));
+    }
+
+    // abilities in use
+    state.sim.abilities.active.iter().for_each(|a| {
+        let radius = a.ability.def().radius;
+        if radius <= 0.0 {
+            return;
+        }
+
+        let color = match a.ability {
+            Ability::Sanctuary => ETERNAL_COLOR,
+            _ => LUMINAL_COLOR,
+        };
+
+        draw.circle(radius)
+            .position(a.pos - radius)
+            .alpha(0.15)
+            .fill_color(color)
+            .fill();
+    });
+
+    draw.circle(state.sim.params.sacred_radius)
         .alpha(0.01)
-        .position(state.mouse_pos - state.params.sacred_radius);
+        .position(state.sim.guide_pos - state.sim.params.sacred_radius);
     draw.image(&state.res.circle)
         .alpha(alpha)
         .size(Vec2::splat(animated_size))
         .anchor(Vec2::splat(0.5))
-        .translate(state.mouse_pos);
+        .translate(state.sim.guide_pos);
+
+    state.sim.souls.iter().for_each(|s| {
+        let soul_pos = s.interpolated_pos(state.alpha);
 
-    state.souls.iter().for_each(|s| {
         // skip if it's not visible
         if !state
             .camera
-            .is_rect_visible(Rect::new(s.pos, Vec2::splat(16.0)))
+            .is_rect_visible(Rect::new(soul_pos, Vec2::splat(16.0)))
         {
             return;
         }
@@ -121,7 +245,7 @@ fn update(state: &mut State) {
             SoulKind::Eternal => (&state.res.eternal, ETERNAL_COLOR, 1.0),
         };
 
-        let pos = s.pos + s.visuals.pos_offset;
+        let pos = soul_pos + s.visuals.pos_offset;
         // shadow
         draw.image(&state.res.neutral)
             .translate(pos + vec2(16.0, state.res.neutral.height()))
@@ -148,6 +272,16 @@ fn update(state: &mut State) {
                 .color(cc);
         }
 
+        // a faint trail shows which souls are traveling
+        if s.is_wanderer() {
+            let dir = (s.pos - s.prev_pos).normalize_or_zero();
+            draw.image(&state.res.karma_white)
+                .anchor(Vec2::splat(0.5))
+                .translate(pos + 8.0 - dir * 6.0)
+                .alpha(0.2)
+                .color(color);
+        }
+
         draw.image(tex).position(pos).alpha(alpha);
         draw.image(&state.res.shirt).position(pos).color(color);
     });
@@ -180,10 +314,10 @@ fn update(state: &mut State) {
         .color(LUMINAL_COLOR)
         .crop(
             Vec2::ZERO,
-            (t_size * vec2(state.good_progress, 1.0)).round(),
+            (t_size * vec2(state.sim.good_progress, 1.0)).round(),
         );
 
-    let bad_progress_width = t_size.x * state.bad_progress;
+    let bad_progress_width = t_size.x * state.sim.bad_progress;
     draw.image(&state.res.bar)
         .anchor(vec2(1.0, 0.0))
         .translate(vec2(xx + t_size.x * scale, 20.0))
@@ -212,18 +346,62 @@ fn update(state: &mut State) {
         .energy_positions
         .retain(|p| !is_close(*p, spirit_target, 16.0));
 
+    // abilities
+    Ability::ALL.iter().enumerate().for_each(|(i, a)| {
+        let def = a.def();
+        let size = vec2(80.0, 36.0);
+        let pos = vec2(20.0 + i as f32 * (size.x + 8.0), win_size.y - size.y - 40.0);
+        let unlocked = state.sim.blessings.has_ability(*a);
+        let cooldown = state.sim.abilities.cooldown(*a);
+
+        let color = if state.sim.can_cast(*a) {
+            ETERNAL_COLOR
+        } else {
+            Color::GRAY
+        };
+
+        draw.rect(pos, size)
+            .alpha(if unlocked { 0.8 } else { 0.3 })
+            .fill_color(Color::BLACK)
+            .fill()
+            .stroke_color(color)
+            .stroke(2.0);
+
+        if !unlocked {
+            return;
+        }
+
+        draw.text(&format!("{} {}", i + 1, def.name))
+            .color(color)
+            .translate(pos + 6.0)
+            .size(8.0);
+
+        let info = if cooldown > 0.0 {
+            format!("{cooldown:.0}s")
+        } else {
+            def.cost.to_string()
+        };
+
+        draw.image(&state.res.souls_icon)
+            .position(pos + vec2(6.0, 16.0));
+        draw.text(&info)
+            .translate(pos + vec2(26.0, 24.0))
+            .anchor(vec2(0.0, 0.5))
+            .size(8.0);
+    });
+
     // blessings
     let mut tooltip: Option<(Blessing, Vec2)> = None;
     let offset = Vec2::splat(20.0);
     let padding = Vec2::splat(48.0);
     let grid_size = 3;
-    Blessing::iter().enumerate().for_each(|(i, b)| {
+    Blessing::all().enumerate().for_each(|(i, b)| {
         let grid = vec2((i % grid_size) as f32, (i / grid_size) as f32);
         let pos = offset + padding * grid;
 
-        let lvl = state.blessings.level(&b);
-        let price = b.price(lvl);
-        let can_unlock = state.blessings.can_unlock(b);
+        let lvl = state.sim.blessings.level(&b);
+        let price = state.sim.blessing_price(b);
+        let can_unlock = state.sim.can_unlock(b);
 
         let alpha = if lvl == 0 && can_unlock {
             0.8
@@ -233,7 +411,7 @@ fn update(state: &mut State) {
             0.3
         };
 
-        let mut color = if can_unlock && state.energy >= price {
+        let mut color = if can_unlock && state.sim.energy >= price {
             ETERNAL_COLOR
         } else if lvl >= 1 {
             Color::WHITE
@@ -259,11 +437,11 @@ fn update(state: &mut State) {
             .color(color);
     });
 
-    Blessing::iter().enumerate().for_each(|(i, b)| {
+    Blessing::all().enumerate().for_each(|(i, b)| {
         let grid = vec2((i % grid_size) as f32, (i / grid_size) as f32);
         let pos = offset + padding * grid;
 
-        let lvl = state.blessings.level(&b);
+        let lvl = state.sim.blessings.level(&b);
         if lvl != 0 {
             draw.text(&lvl.to_string())
                 .size(14.0)
@@ -273,34 +451,99 @@ fn update(state: &mut State) {
         }
     });
 
-    draw.text(&state.energy.to_string())
+    draw.text(&state.sim.energy.to_string())
         .anchor(vec2(1.0, 0.5))
         .translate(vec2(win_size.x - 60.0, 20.0 + 16.0))
         .size(16.0);
 
-    draw.text(&format!("{:.0}%", state.good_progress * 100.0))
+    if state.sim.is_versus() {
+        draw_rival_panel(&mut draw, state, scale);
+    }
+
+    if state.sim.is_endless() {
+        draw.text(&format!("Score: {}", state.sim.score().total()))
+            .anchor(vec2(1.0, 0.5))
+            .translate(vec2(win_size.x - 20.0, 70.0))
+            .color(ETERNAL_COLOR)
+            .size(16.0);
+    }
+
+    if let Some(daily) = &state.sim.daily {
+        daily.modifiers.iter().enumerate().for_each(|(i, m)| {
+            draw.text(m.description())
+                .anchor(vec2(1.0, 0.5))
+                .translate(vec2(win_size.x - 20.0, 66.0 + i as f32 * 12.0))
+                .color(SHADOW_COLOR)
+                .size(8.0);
+        });
+    }
+
+    if let (Some(win), true) = (&state.sim.win, state.sim.level.is_some()) {
+        draw.text(&win.description())
+            .anchor(vec2(1.0, 0.5))
+            .translate(vec2(win_size.x - 20.0, 66.0))
+            .color(Color::GRAY)
+            .size(8.0);
+
+        if let Some(progress) = win.progress(&state.sim.snapshot()) {
+            draw.text(&progress)
+                .anchor(vec2(1.0, 0.5))
+                .translate(vec2(win_size.x - 20.0, 80.0))
+                .color(ETERNAL_COLOR)
+                .size(12.0);
+        }
+    }
+
+    draw.text(&format!("{:.0}%", state.sim.good_progress * 100.0))
         .anchor(vec2(1.0, 0.5))
         .translate(vec2(xx - 6.0, 20.0 + 16.0))
         .size(12.0);
 
-    draw.text(&format!("{:.0}%", state.bad_progress * 100.0))
+    draw.text(&format!("{:.0}%", state.sim.bad_progress * 100.0))
         .anchor(vec2(0.0, 0.5))
         .translate(vec2(xx + t_size.x * scale + 6.0, 20.0 + 16.0))
         .size(12.0);
 
+    let blocked = state.sim.params.block_spawn_souls;
     draw.text(&format!(
         "Next wave: {:.1}s ({} souls)",
-        state.spawn_timer - state.params.slow_spawn_time,
-        state
-            .spawn_num
-            .checked_sub(state.params.block_spawn_souls)
-            .unwrap_or(1)
+        state.sim.waves.timer,
+        state.sim.waves.next_count(blocked)
     ))
     .anchor(vec2(0.5, 0.0))
     .h_align_center()
     .translate(vec2(win_size.x * 0.5, 60.0))
     .size(8.0);
 
+    // warn the player before the waves come, blinking for the special ones
+    if let Some(a) = state.sim.waves.announcement(state.sim.play_time, blocked) {
+        let (color, size) = if a.is_special {
+            (SHADOW_COLOR, 16.0)
+        } else {
+            (Color::WHITE, 10.0)
+        };
+        let alpha = if a.is_special {
+            0.6 + 0.4 * (elapsed * 6.0).sin().abs()
+        } else {
+            0.8
+        };
+
+        draw.text(&format!("{} ({:.0}s)", a.text, a.time_left.max(0.0).ceil()))
+            .anchor(vec2(0.5, 0.0))
+            .h_align_center()
+            .translate(vec2(win_size.x * 0.5, 74.0))
+            .color(color.with_alpha(alpha))
+            .size(size);
+    }
+
+    if state.is_playback() {
+        draw.text("REPLAY")
+            .anchor(vec2(0.5, 1.0))
+            .translate(vec2(win_size.x * 0.5, win_size.y - 20.0))
+            .color(ETERNAL_COLOR)
+            .size(12.0);
+    }
+
     if let Some((b, pos)) = tooltip {
         let size = vec2(250.0, 200.0);
         let pos = pos + 16.0;
@@ -312,10 +555,10 @@ fn update(state: &mut State) {
             .stroke(4.0);
 
         let (name, desc) = b.info();
-        let lvl = state.blessings.level(&b);
+        let lvl = state.sim.blessings.level(&b);
         let max_lvl = b.levels();
         let price = if lvl < max_lvl {
-            Some(b.price(lvl))
+            Some(state.sim.blessing_price(b))
         } else {
             None
         };
@@ -343,7 +586,7 @@ fn update(state: &mut State) {
                 .translate(base_pos)
                 .size(10.0);
 
-            let img_pos = base_pos + Vec2::Y * 20.0;
+            let mut img_pos = base_pos + Vec2::Y * 20.0;
             draw.image(&state.res.souls_icon).position(img_pos);
 
             draw.text(&price.to_string())
@@ -351,22 +594,47 @@ fn update(state: &mut State) {
                 .anchor(vec2(0.0, 0.5))
                 .size(9.0);
 
-            if let Some((lvl, rb)) = b.require() {
-                let idx = rb as usize;
-                let tex = &state.res.blessings[idx];
-                let img_pos = img_pos + Vec2::Y * 20.0;
-                draw.image(tex).position(img_pos);
-
-                draw.text(&lvl.to_string())
-                    .translate(img_pos + vec2(20.0, 8.0))
+            let is_allowed = match &state.sim.level {
+                Some(l) => l.allows(b),
+                None => true,
+            };
+            if !is_allowed {
+                img_pos += Vec2::Y * 20.0;
+                draw.text("Not available in this level")
+                    .color(SHADOW_COLOR)
+                    .translate(img_pos + vec2(0.0, 8.0))
                     .anchor(vec2(0.0, 0.5))
                     .size(9.0);
+            } else if let Err(locked) = state.sim.blessings.can_unlock(b) {
+                locked.missing.iter().for_each(|p| {
+                    img_pos += Vec2::Y * 20.0;
+                    draw_prerequisite(&mut draw, state, p, img_pos);
+                });
+
+                locked.excluded_by.iter().for_each(|eb| {
+                    img_pos += Vec2::Y * 20.0;
+                    draw.text(&format!("Blocked by {}", eb.info().0))
+                        .color(SHADOW_COLOR)
+                        .translate(img_pos + vec2(0.0, 8.0))
+                        .anchor(vec2(0.0, 0.5))
+                        .size(9.0);
+                });
             }
         }
 
-        // if click ask for upgrade
+        if lvl > b.def().initial_level {
+            draw.text("Right click to refund")
+                .color(Color::GRAY)
+                .anchor(vec2(1.0, 1.0))
+                .translate(pos + size - 10.0)
+                .size(8.0);
+        }
+
+        // if click ask for upgrade, right click gives the last level back
         if is_mouse_btn_pressed(MouseButton::Left) {
-            state.unlock_blessing(b);
+            state.queue_unlock(b);
+        } else if is_mouse_btn_pressed(MouseButton::Right) {
+            state.queue_respec(b);
         }
     }
 
@@ -381,15 +649,130 @@ fn update(state: &mut State) {
         .size(6.0);
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    if !state.is_playback() && !state.sim.is_versus() {
+        draw.text("F5: Save game")
+            .anchor(vec2(1.0, 1.0))
+            .translate(win_size - vec2(10.0, 20.0))
+            .color(Color::GRAY)
+            .size(6.0);
+
+        if matches!(state.mode, Mode::Playing) && is_key_pressed(KeyCode::F5) {
+            if let Err(e) = state.save_game() {
+                eprintln!("Error saving game: {e}");
+            }
+        }
+    }
+
     gfx::render_to_frame(&draw).unwrap();
 
     match state.mode {
         Mode::Win => draw_end(true, state),
         Mode::Defeat => draw_end(false, state),
+        Mode::LevelComplete => draw_level_complete(state),
         _ => {}
     }
 }
 
+/// Energy and blessings of the second player, mirrored on the right side
+fn draw_rival_panel(draw: &mut Draw2D, state: &State, scale: f32) {
+    let Some(rival) = &state.sim.rival else {
+        return;
+    };
+
+    let win_size = window_size();
+    draw.text(&format!("P2: {}", rival.energy))
+        .anchor(vec2(1.0, 0.5))
+        .translate(vec2(win_size.x - 20.0, 70.0))
+        .color(SHADOW_COLOR)
+        .size(16.0);
+
+    let padding = Vec2::splat(48.0);
+    let grid_size = 3;
+    let rows = Blessing::all().count().div_ceil(grid_size);
+    Blessing::all().enumerate().for_each(|(i, b)| {
+        let col = grid_size - 1 - i % grid_size;
+        let grid = vec2(col as f32, (i / grid_size) as f32);
+        let pos = vec2(win_size.x - 20.0 - 16.0 * scale, 90.0) - padding * vec2(grid.x, -grid.y);
+
+        let lvl = rival.blessings.level(&b);
+        let color = if rival.can_buy(b) {
+            SHADOW_COLOR
+        } else if lvl >= 1 {
+            Color::WHITE
+        } else {
+            Color::BLACK
+        };
+
+        let alpha = if !b.is_for_rival() {
+            0.1
+        } else if lvl >= 1 {
+            1.0
+        } else {
+            0.5
+        };
+
+        draw.image(&state.res.blessings[i])
+            .scale(Vec2::splat(scale))
+            .translate(pos)
+            .alpha(alpha)
+            .color(color);
+
+        if lvl != 0 {
+            draw.text(&lvl.to_string())
+                .size(14.0)
+                .color(SHADOW_COLOR)
+                .translate(pos + 32.0)
+                .anchor(Vec2::splat(0.5));
+        }
+
+        if i == state.rival_selected {
+            draw.rect(pos - 2.0, Vec2::splat(16.0 * scale + 4.0))
+                .stroke_color(SHADOW_COLOR)
+                .stroke(2.0);
+
+            let price = (lvl < b.levels()).then(|| b.price(lvl).to_string());
+            draw.text(&format!("{} {}", b.info().0, price.unwrap_or_default()))
+                .anchor(vec2(1.0, 0.0))
+                .translate(vec2(win_size.x - 20.0, 90.0 + rows as f32 * padding.y))
+                .color(Color::GRAY)
+                .size(8.0);
+        }
+    });
+}
+
+/// Draws a blessing requirement in a row and returns the width used
+fn draw_prerequisite(draw: &mut Draw2D, state: &State, p: &Prerequisite, pos: Vec2) -> f32 {
+    let mut draw_list = |list: &[Prerequisite], separator: &str| {
+        let mut width = 0.0;
+        list.iter().enumerate().for_each(|(i, p)| {
+            if i > 0 {
+                draw.text(separator)
+                    .color(Color::GRAY)
+                    .translate(pos + vec2(width, 8.0))
+                    .anchor(vec2(0.0, 0.5))
+                    .size(9.0);
+                width += 8.0 * separator.len() as f32;
+            }
+            width += draw_prerequisite(draw, state, p, pos + Vec2::X * width);
+        });
+        width
+    };
+
+    match p {
+        Prerequisite::Level(b, lvl) => {
+            draw.image(&state.res.blessings[b.index()]).position(pos);
+            draw.text(&lvl.to_string())
+                .translate(pos + vec2(20.0, 8.0))
+                .anchor(vec2(0.0, 0.5))
+                .size(9.0);
+            40.0
+        }
+        Prerequisite::AllOf(list) => draw_list(list, "+ "),
+        Prerequisite::AnyOf(list) => draw_list(list, "or "),
+    }
+}
+
 pub fn lerp_color(c1: Color, c2: Color, t: f32) -> Color {
     c1 + (c2 - c1) * t
 }
@@ -417,7 +800,30 @@ pub fn draw_menu(state: &mut State) {
         .color(Color::WHITE)
         .size(20.0);
 
-    draw.text("Your mision is to reach at least 98% influence (blue) before it drops to zero! Move the camera with WASD, and use the left mouse button to guide the good souls to follow you, turning other souls blue. Keep an eye on your spiritual energy to unlock blessings (top-left) for extra perks. Manage the balance between light and shadow, and don’t let your influence fade away!")
+    let difficulty = &state.presets[state.difficulty];
+    draw.text(&format!("< Difficulty: {} >", difficulty.name))
+        .anchor(Vec2::splat(0.5))
+        .translate(window_size() * 0.5 - Vec2::Y * 30.0)
+        .h_align_center()
+        .color(ETERNAL_COLOR)
+        .size(12.0);
+
+    let map_text = if state.is_generated_map() {
+        format!(
+            "Map: {} (UP/DOWN to change, G to generate, E to export)",
+            state.sim.map.name
+        )
+    } else {
+        format!("Map: {} (UP/DOWN to change)", state.sim.map.name)
+    };
+    draw.text(&map_text)
+        .anchor(Vec2::splat(0.5))
+        .translate(window_size() * 0.5 - Vec2::Y * 48.0)
+        .h_align_center()
+        .color(Color::GRAY)
+        .size(10.0);
+
+    draw.text(&format!("Your mision is to reach at least {:.0}% influence (blue) before it drops to zero! Move the camera with WASD, and use the left mouse button to guide the good souls to follow you, turning other souls blue. Keep an eye on your spiritual energy to unlock blessings (top-left) for extra perks. Manage the balance between light and shadow, and don’t let your influence fade away!", difficulty.win_threshold * 100.0))
         .anchor(vec2(0.5, 1.0))
         .translate(vec2(window_width() * 0.5, window_height() - 50.0))
         .h_align_center()
@@ -431,27 +837,167 @@ pub fn draw_menu(state: &mut State) {
         .size(12.0)
         .color(ETERNAL_COLOR);
 
+    let daily_text = {
+        let day = daily::today();
+        let modifiers = daily::Daily::new(day)
+            .modifiers
+            .iter()
+            .map(|m| m.description())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let best = state
+            .daily_records
+            .best(day)
+            .map_or("not played yet".to_string(), |r| r.summary());
+        format!(
+            "Press T for the daily challenge of {} (best: {best})\n{modifiers}",
+            daily::date(day)
+        )
+    };
+
+    let mut options = vec![
+        "Press C for the campaign",
+        "Press V for a local match, Light vs Shadow",
+        "Press N for endless survival",
+        &daily_text,
+    ];
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if ld56::storage::exists(SAVE_FILE) {
+            options.push("Press L to continue your saved game");
+        }
+        if ld56::storage::exists(LAST_REPLAY_FILE) {
+            options.push("Press R to watch your last run");
+        }
+    }
+
+    draw.text(&options.join("\n"))
+        .anchor(vec2(0.5, 0.0))
+        .translate(window_size() * 0.5 + Vec2::Y * 30.0)
+        .h_align_center()
+        .color(Color::GRAY)
+        .size(10.0);
+
+    if let Some(error) = &state.menu_error {
+        draw.text(error)
+            .anchor(vec2(0.5, 1.0))
+            .translate(window_size() * 0.5 - Vec2::Y * 66.0)
+            .h_align_center()
+            .max_width(window_width() * 0.8)
+            .color(SHADOW_COLOR)
+            .size(10.0);
+    }
+
     gfx::render_to_frame(&draw).unwrap();
 
+    // the last error stays on screen until the player does something else
     if !keys_pressed().is_empty() {
-        state.mode = Mode::Playing;
-        init_spawn(state);
+        state.menu_error = None;
+    }
+
+    // change difficulty
+    let presets = state.presets.len();
+    if is_key_pressed(KeyCode::ArrowLeft) {
+        state.set_difficulty((state.difficulty + presets - 1) % presets);
+        return;
+    } else if is_key_pressed(KeyCode::ArrowRight) {
+        state.set_difficulty((state.difficulty + 1) % presets);
+        return;
+    }
+
+    // change map
+    let maps = state.maps.len();
+    if is_key_pressed(KeyCode::ArrowUp) {
+        state.set_map((state.map + maps - 1) % maps);
+        return;
+    } else if is_key_pressed(KeyCode::ArrowDown) {
+        state.set_map((state.map + 1) % maps);
+        return;
+    }
+
+    if state.is_generated_map() {
+        if is_key_pressed(KeyCode::KeyG) {
+            state.generate_map();
+            return;
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if is_key_pressed(KeyCode::KeyE) {
+            match state.export_map() {
+                Ok(id) => println!("Map exported as '{id}'"),
+                Err(e) => state.menu_error = Some(format!("Error exporting map: {e}")),
+            }
+            return;
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if is_key_pressed(KeyCode::KeyL) {
+            if let Err(e) = state.load_game() {
+                state.menu_error = Some(format!("Error loading game: {e}"));
+            }
+            return;
+        }
+
+        if is_key_pressed(KeyCode::KeyR) {
+            if let Err(e) = Replay::load(LAST_REPLAY_FILE).and_then(|r| state.start_playback(r)) {
+                state.menu_error = Some(format!("Error loading replay: {e}"));
+            }
+            return;
+        }
+    }
+
+    if is_key_pressed(KeyCode::KeyC) {
+        state.open_level_select();
+        return;
+    }
+
+    if is_key_pressed(KeyCode::KeyT) {
+        if let Err(e) = state.start_daily(daily::today()) {
+            state.menu_error = Some(format!("Error starting the daily challenge: {e}"));
+        }
+        return;
+    }
+
+    if is_key_pressed(KeyCode::KeyV) {
+        state.start_versus();
+        return;
+    }
+
+    if is_key_pressed(KeyCode::KeyN) {
+        state.start_endless();
+        return;
+    }
+
+    if !keys_pressed().is_empty() {
+        state.start_game();
     }
 }
 
 fn draw_end(winning: bool, state: &mut State) {
-    let (win_text, color, v_offset) = if winning {
+    let (win_text, color, v_offset) = if state.sim.level.is_some() {
+        ("Level failed", SHADOW_COLOR, 70.0)
+    } else if state.sim.is_endless() {
+        (
+            "The shadows have overwhelmed you.\nYour light lasted as long as it could",
+            SHADOW_COLOR,
+            70.0,
+        )
+    } else if state.sim.is_versus() {
+        if winning {
+            ("Player 1 wins!\nThe light prevails", LUMINAL_COLOR, 40.0)
+        } else {
+            ("Player 2 wins!\nThe shadows prevail", SHADOW_COLOR, 40.0)
+        }
+    } else if winning {
         (
             "You've won!\nThe light of karma shines bright",
             ETERNAL_COLOR,
             40.0,
         )
     } else {
-        (
-            "The shadows have overwhelmed you.\nYour influence has faded into darkness",
-            SHADOW_COLOR,
-            70.0,
-        )
+        ("The shadows have overwhelmed you", SHADOW_COLOR, 70.0)
     };
 
     let mut draw = create_draw_2d();
@@ -468,7 +1014,19 @@ fn draw_end(winning: bool, state: &mut State) {
         .max_width(window_width() * 0.8)
         .size(30.0);
 
-    let (good, bad, neutral) = state.souls.iter().fold((0, 0, 0), |(g, b, n), s| {
+    // explain which of the conditions ended the game
+    if let Some(reason) = &state.sim.end_reason {
+        let title = draw.last_text_bounds();
+        draw.text(reason)
+            .color(Color::WHITE)
+            .h_align_center()
+            .anchor(vec2(0.5, 0.0))
+            .translate(vec2(window_width() * 0.5, 66.0 + title.height()))
+            .max_width(window_width() * 0.8)
+            .size(14.0);
+    }
+
+    let (good, bad, neutral) = state.sim.souls.iter().fold((0, 0, 0), |(g, b, n), s| {
         if s.is_good() {
             (g + 1, b, n)
         } else if s.is_bad() {
@@ -478,12 +1036,48 @@ fn draw_end(winning: bool, state: &mut State) {
         }
     });
 
-    let text = format!(
-        "You've converted '{good}' souls, while the enemy captured '{bad}' out of '{}' total. There were '{neutral}' neutral souls remaining. You gathered '{}' spiritual energy, and your total playtime was {:.0} seconds.",
-        state.souls.len(),
-        state.total_energy,
-        state.play_time
-    );
+    let score = state.sim.score();
+    let text = match &state.sim.rival {
+        _ if state.sim.is_endless() => format!(
+            "Score: {}\n\nSurvived {:.0} seconds: {} points\nConverted '{}' souls: {} points\nGathered '{}' spiritual energy: {} points",
+            score.total(),
+            state.sim.play_time,
+            score.time,
+            state.sim.conversions,
+            score.conversions,
+            state.sim.total_energy,
+            score.energy
+        ),
+        Some(rival) => format!(
+            "Light: '{good}' souls, '{}' spiritual energy gathered.\nShadow: '{bad}' souls, '{}' spiritual energy gathered.\nThere were '{neutral}' neutral souls remaining out of '{}' total, and the match lasted {:.0} seconds.",
+            state.sim.total_energy,
+            rival.total_energy,
+            state.sim.souls.len(),
+            state.sim.play_time
+        ),
+        None => format!(
+            "You've converted '{good}' souls, while the enemy captured '{bad}' out of '{}' total. There were '{neutral}' neutral souls remaining. You gathered '{}' spiritual energy, and your total playtime was {:.0} seconds.",
+            state.sim.souls.len(),
+            state.sim.total_energy,
+            state.sim.play_time
+        ),
+    };
+
+    let text = match (&state.sim.daily, state.is_playback()) {
+        (Some(d), false) => {
+            let best = state.daily_records.best(d.day).map(|r| r.summary());
+            let record = if state.is_daily_best {
+                "New best for today!".to_string()
+            } else {
+                format!("Today's best: {}", best.unwrap_or_default())
+            };
+            format!(
+                "{text}\n\nDaily challenge of {}. {record}",
+                daily::date(d.day)
+            )
+        }
+        _ => text,
+    };
 
     draw.text(&text)
         .size(16.0)
@@ -493,16 +1087,196 @@ fn draw_end(winning: bool, state: &mut State) {
         .anchor(Vec2::splat(0.5))
         .translate(window_size() * 0.5 + Vec2::Y * v_offset);
 
-    draw.text("Press SPACE to RESTART")
+    draw.text(&format!("Seed: {}", state.sim.rng.seed()))
+        .size(8.0)
+        .color(Color::GRAY)
+        .anchor(vec2(0.0, 1.0))
+        .position(vec2(10.0, window_height() - 20.0));
+
+    let restart_text = if state.sim.level.is_some() {
+        "Press SPACE to RETRY, ESC for the level select"
+    } else {
+        "Press SPACE to RESTART"
+    };
+    draw.text(restart_text)
         .size(20.0)
         .color(Color::WHITE)
         .h_align_center()
         .anchor(vec2(0.5, 1.0))
         .translate(vec2(window_width() * 0.5, window_height() - 50.0));
 
-    if is_key_pressed(KeyCode::Space) {
+    if state.sim.level.is_some() {
+        if is_key_pressed(KeyCode::Space) {
+            if let Err(e) = state.start_level(state.level) {
+                eprintln!("Error starting level: {e}");
+            }
+        } else if is_key_pressed(KeyCode::Escape) {
+            state.open_level_select();
+        }
+    } else if is_key_pressed(KeyCode::Space) {
+        let (difficulty, map) = (state.difficulty, state.map);
         *state = setup();
+        state.set_difficulty(difficulty);
+        state.set_map(map);
     }
 
     gfx::render_to_frame(&draw).unwrap();
 }
+
+fn draw_level_select(state: &mut State) {
+    let mut draw = create_draw_2d();
+    draw.text("Campaign")
+        .anchor(vec2(0.5, 0.0))
+        .translate(vec2(window_width() * 0.5, 50.0))
+        .size(40.0);
+
+    let completed = state
+        .levels
+        .iter()
+        .filter(|l| state.progress.is_completed(&l.id))
+        .count();
+    draw.text(&format!(
+        "{completed} of {} levels completed",
+        state.levels.len()
+    ))
+    .anchor(vec2(0.5, 0.0))
+    .translate(vec2(window_width() * 0.5, 100.0))
+    .color(Color::GRAY)
+    .size(12.0);
+
+    state.levels.iter().enumerate().for_each(|(i, l)| {
+        let (status, color) = if state.progress.is_completed(&l.id) {
+            ("completed", LUMINAL_COLOR)
+        } else if state.is_level_available(i) {
+            ("", Color::WHITE)
+        } else {
+            ("locked", Color::GRAY)
+        };
+
+        let color = if i == state.level {
+            ETERNAL_COLOR
+        } else {
+            color
+        };
+        let text = if status.is_empty() {
+            format!("{}. {}", i + 1, l.name)
+        } else {
+            format!("{}. {} ({status})", i + 1, l.name)
+        };
+        draw.text(&text)
+            .anchor(vec2(0.5, 0.0))
+            .translate(vec2(window_width() * 0.5, 140.0 + i as f32 * 22.0))
+            .color(color)
+            .size(14.0);
+    });
+
+    if let Some(level) = state.levels.get(state.level) {
+        let y = 150.0 + state.levels.len() as f32 * 22.0;
+        draw.text(&level.description)
+            .anchor(vec2(0.5, 0.0))
+            .translate(vec2(window_width() * 0.5, y))
+            .h_align_center()
+            .max_width(window_width() * 0.6)
+            .color(Color::GRAY)
+            .size(12.0);
+
+        draw.text(&format!(
+            "Objective: {} ({})",
+            level.goal(),
+            level.difficulty
+        ))
+        .anchor(vec2(0.5, 0.0))
+        .translate(vec2(window_width() * 0.5, y + 40.0))
+        .h_align_center()
+        .max_width(window_width() * 0.6)
+        .color(ETERNAL_COLOR)
+        .size(12.0);
+    }
+
+    draw.text("UP/DOWN to select, ENTER to play, ESC to go back")
+        .anchor(vec2(0.5, 1.0))
+        .translate(vec2(window_width() * 0.5, window_height() - 50.0))
+        .color(Color::GRAY)
+        .size(10.0);
+
+    gfx::render_to_frame(&draw).unwrap();
+
+    let levels = state.levels.len();
+    if is_key_pressed(KeyCode::ArrowUp) {
+        state.level = (state.level + levels - 1) % levels;
+    } else if is_key_pressed(KeyCode::ArrowDown) {
+        state.level = (state.level + 1) % levels;
+    } else if is_key_pressed(KeyCode::Escape) {
+        state.open_menu();
+    } else if is_key_pressed(KeyCode::Enter) && state.is_level_available(state.level) {
+        if let Err(e) = state.start_level(state.level) {
+            eprintln!("Error starting level: {e}");
+        }
+    }
+}
+
+fn draw_level_complete(state: &mut State) {
+    let Some(level) = &state.sim.level else {
+        return;
+    };
+
+    let mut draw = create_draw_2d();
+
+    draw.rect(Vec2::ZERO, window_size())
+        .color(Color::BLACK)
+        .alpha(0.99);
+
+    let has_next = state.level + 1 < state.levels.len();
+    let title = if has_next {
+        format!("{} completed!\nThe light grows stronger", level.name)
+    } else {
+        format!(
+            "{} completed!\nThe whole realm shines with your light",
+            level.name
+        )
+    };
+
+    draw.text(&title)
+        .color(ETERNAL_COLOR)
+        .h_align_center()
+        .anchor(vec2(0.5, 0.0))
+        .translate(vec2(window_width() * 0.5, 50.0))
+        .max_width(window_width() * 0.8)
+        .size(30.0);
+
+    draw.text(&format!(
+        "{}.\n\nYou converted '{}' souls and gathered '{}' spiritual energy in {:.0} seconds.",
+        state.sim.end_reason.as_deref().unwrap_or_default(),
+        state.sim.conversions,
+        state.sim.total_energy,
+        state.sim.play_time
+    ))
+    .size(16.0)
+    .color(Color::GRAY)
+    .h_align_center()
+    .max_width(window_width() * 0.6)
+    .anchor(Vec2::splat(0.5))
+    .translate(window_size() * 0.5 + Vec2::Y * 40.0);
+
+    let next_text = if has_next {
+        "Press SPACE for the next level, ESC for the level select"
+    } else {
+        "Press SPACE for the level select"
+    };
+    draw.text(next_text)
+        .size(20.0)
+        .color(Color::WHITE)
+        .h_align_center()
+        .anchor(vec2(0.5, 1.0))
+        .translate(vec2(window_width() * 0.5, window_height() - 50.0));
+
+    gfx::render_to_frame(&draw).unwrap();
+
+    if is_key_pressed(KeyCode::Space) && has_next {
+        if let Err(e) = state.start_level(state.level + 1) {
+            eprintln!("Error starting level: {e}");
+        }
+    } else if is_key_pressed(KeyCode::Space) || is_key_pressed(KeyCode::Escape) {
+        state.open_level_select();
+    }
+}
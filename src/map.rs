@@ -0,0 +1,288 @@
+use crate::config::check_range;
+use crate::mapgen::{generate, GENERATED_PREFIX};
+use crate::souls::SoulKind;
+use crate::terrain::Obstacle;
+use crate::waves::{WaveDef, WaveLocation};
+use glam::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_MAP: &str = "meadow";
+/// Id of the exported generated maps, they can't keep the generated id because those
+/// are always built from the seed, or the replays would change after editing the file
+pub const EXPORTED_PREFIX: &str = "exported-";
+
+const MAPS_DIR: &str = "assets/maps";
+
+// maps bundled with the game, in the same order they're shown in the menu
+const MAPS: [(&str, &str); 3] = [
+    ("meadow", include_str!("../assets/maps/meadow.ron")),
+    ("crossing", include_str!("../assets/maps/crossing.ron")),
+    ("canyon", include_str!("../assets/maps/canyon.ron")),
+];
+
+/// Souls placed in a circle when the game starts
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct SpawnGroup {
+    pub count: usize,
+    #[serde(default)]
+    pub kind: Option<SoulKind>,
+    /// Chance of each soul to be a wanderer
+    #[serde(default)]
+    pub wanderers: f32,
+    pub pos: (f32, f32),
+    pub radius: f32,
+}
+
+impl SpawnGroup {
+    pub fn pos(&self) -> Vec2 {
+        vec2(self.pos.0, self.pos.1)
+    }
+}
+
+/// Checks the souls placed at the beginning, used by the maps and by the levels that replace them
+pub(crate) fn check_groups(groups: &[SpawnGroup], map_size: Vec2) -> Result<(), String> {
+    groups.iter().try_for_each(|g| {
+        check_range("groups.pos.x", g.pos.0, 0.0, map_size.x)?;
+        check_range("groups.pos.y", g.pos.1, 0.0, map_size.y)?;
+        check_range("groups.radius", g.radius, 0.0, 10000.0)?;
+        check_range("groups.wanderers", g.wanderers, 0.0, 1.0)
+    })?;
+
+    if groups.iter().all(|g| g.count == 0) {
+        return Err("'groups' must spawn at least one soul".to_string());
+    }
+
+    Ok(())
+}
+
+/// Portal where the waves bring new souls
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SpawnPoint {
+    pub name: String,
+    pub pos: (f32, f32),
+    pub radius: f32,
+    /// Chance to be picked by the waves without a location, relative to the other points
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+    /// Extra weight of each kind of soul for the arrivals, added to the ones of the wave
+    #[serde(default)]
+    pub kinds: Vec<(SoulKind, f32)>,
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+impl SpawnPoint {
+    pub fn pos(&self) -> Vec2 {
+        vec2(self.pos.0, self.pos.1)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ZoneKind {
+    Shrine,
+    CorruptedPool,
+    Sanctuary,
+}
+
+/// Special area of the map
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Zone {
+    pub kind: ZoneKind,
+    pub pos: (f32, f32),
+    pub radius: f32,
+}
+
+impl Zone {
+    pub fn pos(&self) -> Vec2 {
+        vec2(self.pos.0, self.pos.1)
+    }
+}
+
+/// Layout of the world, difficulty presets are applied on top of it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapDef {
+    // file name without the extension
+    #[serde(skip)]
+    pub id: String,
+    pub name: String,
+    pub size: (f32, f32),
+    /// (column, row) in the 16x16 grid of sprites.png where the 80x80 background tile starts
+    #[serde(default = "default_background")]
+    pub background: (u32, u32),
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+    pub groups: Vec<SpawnGroup>,
+    /// The waves use the center of the map if there are none
+    #[serde(default)]
+    pub spawn_points: Vec<SpawnPoint>,
+    #[serde(default)]
+    pub zones: Vec<Zone>,
+    /// Special waves that come at a fixed time, on top of the regular ones
+    #[serde(default)]
+    pub waves: Vec<WaveDef>,
+}
+
+fn default_background() -> (u32, u32) {
+    (0, 4)
+}
+
+impl MapDef {
+    pub fn size(&self) -> Vec2 {
+        vec2(self.size.0, self.size.1)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        check_range("size.x", self.size.0, 200.0, 10000.0)?;
+        check_range("size.y", self.size.1, 200.0, 10000.0)?;
+
+        let check_pos = |name: &str, pos: (f32, f32)| {
+            check_range(&format!("{name}.x"), pos.0, 0.0, self.size.0)?;
+            check_range(&format!("{name}.y"), pos.1, 0.0, self.size.1)
+        };
+
+        self.obstacles.iter().try_for_each(|o| {
+            check_range("obstacles.size.x", o.size.0, 1.0, self.size.0)?;
+            check_range("obstacles.size.y", o.size.1, 1.0, self.size.1)?;
+            check_range("obstacles.pos.x", o.pos.0, 0.0, self.size.0 - o.size.0)?;
+            check_range("obstacles.pos.y", o.pos.1, 0.0, self.size.1 - o.size.1)
+        })?;
+
+        check_groups(&self.groups, self.size())?;
+
+        self.spawn_points.iter().try_for_each(|p| {
+            check_pos(&format!("spawn_points.{}.pos", p.name), p.pos)?;
+            check_range("spawn_points.radius", p.radius, 0.0, 10000.0)?;
+            check_range("spawn_points.weight", p.weight, 0.0, 1000.0)?;
+            p.kinds
+                .iter()
+                .try_for_each(|(_, w)| check_range("spawn_points.kinds", *w, 0.0, 1000.0))
+        })?;
+
+        self.zones.iter().try_for_each(|z| {
+            check_pos("zones.pos", z.pos)?;
+            check_range("zones.radius", z.radius, 1.0, 10000.0)
+        })?;
+
+        self.waves.iter().try_for_each(|w| {
+            check_range("waves.time", w.time, 0.0, 36000.0)?;
+            check_range("waves.count", w.count as f32, 1.0, 500.0)?;
+            check_range("waves.wanderers", w.wanderers.unwrap_or(0.0), 0.0, 1.0)?;
+            if w.kinds.iter().any(|(_, weight)| *weight < 0.0)
+                || w.kinds.iter().map(|(_, weight)| weight).sum::<f32>() <= 0.0
+            {
+                return Err("'waves.kinds' weights must be positive".to_string());
+            }
+
+            match &w.location {
+                WaveLocation::Point(name) if !self.spawn_points.iter().any(|p| p.name == *name) => {
+                    Err(format!("Unknown spawn point '{name}' in 'waves.location'"))
+                }
+                _ => Ok(()),
+            }
+        })?;
+
+        Ok(())
+    }
+
+    pub fn to_ron(&self) -> Result<String, String> {
+        let config = ron::ser::PrettyConfig::default().depth_limit(2);
+        ron::ser::to_string_pretty(self, config).map_err(|e| e.to_string())
+    }
+
+    /// Writes a generated map next to the bundled ones so it can be edited,
+    /// returns the exported map, it never overwrites a map that already exists
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn export(&self) -> Result<MapDef, String> {
+        let Some(seed) = self.id.strip_prefix(GENERATED_PREFIX) else {
+            return Err(format!("Map '{}' is not a generated map", self.id));
+        };
+
+        let map = MapDef {
+            id: format!("{EXPORTED_PREFIX}{seed}"),
+            name: format!("Exported #{seed}"),
+            ..self.clone()
+        };
+        let file = map_file(&map.id);
+        if std::path::Path::new(&file).exists() {
+            return Err(format!("'{file}' already exists"));
+        }
+
+        std::fs::create_dir_all(MAPS_DIR).map_err(|e| e.to_string())?;
+        std::fs::write(&file, map.to_ron()?).map_err(|e| e.to_string())?;
+        Ok(map)
+    }
+}
+
+pub fn map_ids() -> impl Iterator<Item = &'static str> {
+    MAPS.iter().map(|(id, _)| *id)
+}
+
+/// Ids of the maps exported from generated ones, sorted by name
+#[cfg(not(target_arch = "wasm32"))]
+fn exported_ids() -> Vec<String> {
+    let Ok(dir) = std::fs::read_dir(MAPS_DIR) else {
+        return vec![];
+    };
+
+    let mut ids = dir
+        .filter_map(|entry| {
+            let file = entry.ok()?.file_name().into_string().ok()?;
+            let id = file.strip_suffix(".ron")?;
+            id.starts_with(EXPORTED_PREFIX).then(|| id.to_string())
+        })
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids
+}
+
+/// Loads all the bundled maps, and the exported ones after them
+pub fn load_maps() -> Result<Vec<MapDef>, String> {
+    let mut maps = map_ids().map(load_map).collect::<Result<Vec<_>, _>>()?;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    maps.extend(
+        exported_ids()
+            .iter()
+            .map(|id| load_map(id))
+            .collect::<Result<Vec<_>, _>>()?,
+    );
+
+    Ok(maps)
+}
+
+pub fn load_map(id: &str) -> Result<MapDef, String> {
+    // generated maps always come from the seed, the edited ones are exported with another id
+    if let Some(seed) = id.strip_prefix(GENERATED_PREFIX) {
+        let seed = seed
+            .parse()
+            .map_err(|_| format!("Invalid seed for the generated map '{id}'"))?;
+        return Ok(generate(seed));
+    }
+
+    let file = map_file(id);
+
+    // read it from disk when possible, so designers can tweak it without compiling again
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(s) = std::fs::read_to_string(&file) {
+        return parse_map(id, &s).map_err(|e| format!("Error loading '{file}': {e}"));
+    }
+
+    let (_, s) = MAPS
+        .iter()
+        .find(|(map_id, _)| *map_id == id)
+        .ok_or_else(|| format!("Unknown map '{id}'"))?;
+    parse_map(id, s).map_err(|e| format!("Error loading '{file}': {e}"))
+}
+
+fn map_file(id: &str) -> String {
+    format!("{MAPS_DIR}/{id}.ron")
+}
+
+pub fn parse_map(id: &str, s: &str) -> Result<MapDef, String> {
+    let mut map: MapDef = ron::from_str(s).map_err(|e| e.to_string())?;
+    map.id = id.to_string();
+    map.validate()?;
+    Ok(map)
+}
@@ -0,0 +1,434 @@
+use crate::map::{MapDef, SpawnGroup, SpawnPoint, Zone, ZoneKind};
+use crate::rng::Rng;
+use crate::souls::SoulKind;
+use crate::terrain::{Obstacle, ObstacleKind};
+use crate::waves::{Edge, Formation, WaveDef, WaveLocation};
+use glam::{vec2, Vec2};
+use std::collections::VecDeque;
+use std::f32::consts::TAU;
+
+/// Generated maps use `generated-<seed>` as id, so replays and saves can build them again
+pub const GENERATED_PREFIX: &str = "generated-";
+
+// obstacles are placed on a coarse grid, big enough to leave room for the souls
+const CELL_SIZE: f32 = 40.0;
+// distance between the noise samples in cells, and the same for the detail octave
+const NOISE_SCALE: usize = 6;
+const DETAIL_SCALE: usize = 3;
+const DETAIL_WEIGHT: f32 = 0.35;
+// noise values above the rock threshold are rocks and below the river one are water
+const ROCK_THRESHOLD: f32 = 0.68;
+const RIVER_THRESHOLD: f32 = 0.2;
+// free area around the player's start
+const START_RADIUS: f32 = 120.0;
+const VILLAGE_DISTANCE: f32 = 260.0;
+const VILLAGE_RADIUS: f32 = 90.0;
+const VILLAGE_TRIES: usize = 50;
+const ZONE_RADIUS: f32 = 50.0;
+const EDGE_MARGIN: f32 = 100.0;
+
+pub fn generated_id(seed: u64) -> String {
+    format!("{GENERATED_PREFIX}{seed}")
+}
+
+/// Builds a map from the seed, the same seed always gives the same map
+pub fn generate(seed: u64) -> MapDef {
+    let mut rng = Rng::new(seed);
+    let cols = 24 + rng.index(12);
+    let rows = 24 + rng.index(12);
+    let size = vec2(cols as f32, rows as f32) * CELL_SIZE;
+    let center = (size * 0.5).round();
+
+    let villages = place_villages(size, center, &mut rng);
+    let spawn_points = place_spawn_points(size, &mut rng);
+
+    // areas that must be free of obstacles
+    let mut reserved = vec![(center, START_RADIUS)];
+    reserved.extend(villages.iter().map(|v| (*v, VILLAGE_RADIUS)));
+    reserved.extend(spawn_points.iter().map(|p| (p.pos(), p.radius + CELL_SIZE)));
+
+    let mut grid = Grid::new(cols, rows);
+    grid.scatter_obstacles(&reserved, &mut rng);
+
+    // every place with souls must be reachable from the start, and nothing else can be isolated
+    let start = grid.cell_at(center);
+    villages
+        .iter()
+        .copied()
+        .chain(spawn_points.iter().map(|p| p.pos()))
+        .for_each(|pos| grid.carve(start, grid.cell_at(pos)));
+    grid.fill_unreachable(start);
+
+    let groups = villages
+        .iter()
+        .enumerate()
+        .flat_map(|(i, pos)| {
+            // the first village is the player's one and the last is the farthest from it
+            let kind = match i {
+                0 => Some(SoulKind::Luminal),
+                _ if i == villages.len() - 1 => Some(SoulKind::Shadow),
+                _ => None,
+            };
+            village_groups(*pos, kind, size, &mut rng)
+        })
+        .collect();
+
+    // the shadows start next to a pool, and the villages in between get the rest of the zones
+    let zone = |kind, pos: Vec2| Zone {
+        kind,
+        pos: (pos.x, pos.y),
+        radius: ZONE_RADIUS,
+    };
+    let mut zones = vec![];
+    if villages.len() > 2 {
+        zones.push(zone(ZoneKind::Shrine, villages[1]));
+        zones.push(zone(ZoneKind::CorruptedPool, villages[villages.len() - 1]));
+    }
+    if villages.len() > 3 {
+        zones.push(zone(ZoneKind::Sanctuary, villages[villages.len() - 2]));
+    }
+
+    // the shadows try to take the map by surprise once
+    let (edge, side) = [
+        (Edge::North, "north"),
+        (Edge::South, "south"),
+        (Edge::West, "west"),
+        (Edge::East, "east"),
+    ][rng.index(4)];
+    let waves = vec![WaveDef {
+        time: rng.range(80.0..140.0).round(),
+        name: Some(format!("Shadow incursion from the {side}!")),
+        count: 10,
+        kinds: vec![(SoulKind::Shadow, 0.7), (SoulKind::Neutral, 0.3)],
+        location: WaveLocation::Edge(edge),
+        formation: Formation::Line,
+        wanderers: None,
+    }];
+
+    MapDef {
+        id: generated_id(seed),
+        name: format!("Generated #{seed}"),
+        size: (size.x, size.y),
+        background: (0, 4),
+        obstacles: grid.obstacles(),
+        groups,
+        spawn_points,
+        zones,
+        waves,
+    }
+}
+
+/// Village centers, the first one is the start and they're sorted by the distance to it
+fn place_villages(size: Vec2, center: Vec2, rng: &mut Rng) -> Vec<Vec2> {
+    let count = 4 + rng.index(3);
+    let mut villages = vec![center];
+    for _ in 0..VILLAGE_TRIES {
+        if villages.len() > count {
+            break;
+        }
+
+        let pos = vec2(
+            rng.range(EDGE_MARGIN..size.x - EDGE_MARGIN),
+            rng.range(EDGE_MARGIN..size.y - EDGE_MARGIN),
+        )
+        .round();
+
+        if villages.iter().all(|v| v.distance(pos) >= VILLAGE_DISTANCE) {
+            villages.push(pos);
+        }
+    }
+
+    villages.sort_by(|a, b| a.distance(center).total_cmp(&b.distance(center)));
+    villages
+}
+
+/// Waves come from the middle of two random sides of the map,
+/// the second portal is less busy but the shadows like it more
+fn place_spawn_points(size: Vec2, rng: &mut Rng) -> Vec<SpawnPoint> {
+    let mut sides = vec![
+        ("north", vec2(size.x * 0.5, EDGE_MARGIN * 0.6)),
+        ("south", vec2(size.x * 0.5, size.y - EDGE_MARGIN * 0.6)),
+        ("west", vec2(EDGE_MARGIN * 0.6, size.y * 0.5)),
+        ("east", vec2(size.x - EDGE_MARGIN * 0.6, size.y * 0.5)),
+    ];
+
+    (0..2)
+        .map(|i| {
+            let (name, pos) = sides.remove(rng.index(sides.len()));
+            let (weight, kinds) = match i {
+                0 => (2.0, vec![]),
+                _ => (1.0, vec![(SoulKind::Shadow, 0.4)]),
+            };
+            SpawnPoint {
+                name: name.to_string(),
+                pos: (pos.x.round(), pos.y.round()),
+                radius: 50.0,
+                weight,
+                kinds,
+            }
+        })
+        .collect()
+}
+
+/// A few houses close to each other, the `kind` is for the souls of the first house
+fn village_groups(
+    center: Vec2,
+    kind: Option<SoulKind>,
+    size: Vec2,
+    rng: &mut Rng,
+) -> Vec<SpawnGroup> {
+    let houses = 2 + rng.index(3);
+    (0..houses)
+        .map(|i| {
+            let angle = rng.range(0.0..TAU);
+            let distance = rng.range(0.0..VILLAGE_RADIUS - 35.0);
+            let pos = (center + Vec2::from_angle(angle) * distance)
+                .round()
+                .clamp(Vec2::ZERO, size);
+
+            let (count, kind) = match (i, kind) {
+                (0, Some(k)) => (8, Some(k)),
+                _ if rng.range(0.0..1.0) < 0.5 => (5 + rng.index(6), Some(SoulKind::Neutral)),
+                _ => (4 + rng.index(4), None),
+            };
+
+            SpawnGroup {
+                count,
+                kind,
+                wanderers: 0.15,
+                pos: (pos.x, pos.y),
+                radius: rng.range(20.0..35.0).round(),
+            }
+        })
+        .collect()
+}
+
+/// Coarse grid of the map with the kind of obstacle of each cell
+struct Grid {
+    cols: usize,
+    rows: usize,
+    cells: Vec<Option<ObstacleKind>>,
+}
+
+impl Grid {
+    fn new(cols: usize, rows: usize) -> Self {
+        Self {
+            cols,
+            rows,
+            cells: vec![None; cols * rows],
+        }
+    }
+
+    fn cell_at(&self, pos: Vec2) -> usize {
+        let x = ((pos.x / CELL_SIZE) as usize).min(self.cols - 1);
+        let y = ((pos.y / CELL_SIZE) as usize).min(self.rows - 1);
+        y * self.cols + x
+    }
+
+    fn scatter_obstacles(&mut self, reserved: &[(Vec2, f32)], rng: &mut Rng) {
+        let noise = Noise::new(self.cols, self.rows, NOISE_SCALE, rng);
+        let detail = Noise::new(self.cols, self.rows, DETAIL_SCALE, rng);
+
+        for i in 0..self.cells.len() {
+            let (x, y) = (i % self.cols, i / self.cols);
+            let center = (vec2(x as f32, y as f32) + 0.5) * CELL_SIZE;
+            let is_reserved = reserved
+                .iter()
+                .any(|(pos, radius)| pos.distance(center) < *radius + CELL_SIZE * 0.5);
+            if is_reserved {
+                continue;
+            }
+
+            let value =
+                (noise.sample(x, y) + detail.sample(x, y) * DETAIL_WEIGHT) / (1.0 + DETAIL_WEIGHT);
+            self.cells[i] = if value > ROCK_THRESHOLD {
+                Some(ObstacleKind::Rock)
+            } else if value < RIVER_THRESHOLD {
+                Some(ObstacleKind::River)
+            } else {
+                None
+            };
+        }
+    }
+
+    /// Opens a path between both cells, moving only in straight lines to keep it walkable
+    fn carve(&mut self, from: usize, to: usize) {
+        let (mut x, mut y) = ((from % self.cols) as i32, (from / self.cols) as i32);
+        let (tx, ty) = ((to % self.cols) as i32, (to / self.cols) as i32);
+        loop {
+            self.cells[y as usize * self.cols + x as usize] = None;
+            if (x, y) == (tx, ty) {
+                break;
+            }
+
+            if (tx - x).abs() > (ty - y).abs() {
+                x += (tx - x).signum();
+            } else {
+                y += (ty - y).signum();
+            }
+        }
+    }
+
+    /// Turns into rocks the free cells that can't be reached from `start`
+    fn fill_unreachable(&mut self, start: usize) {
+        let mut visited = vec![false; self.cells.len()];
+        visited[start] = true;
+
+        let mut queue = VecDeque::from([start]);
+        while let Some(cell) = queue.pop_front() {
+            let (x, y) = (cell % self.cols, cell / self.cols);
+            let neighbours = [
+                (x > 0).then(|| cell - 1),
+                (x + 1 < self.cols).then(|| cell + 1),
+                (y > 0).then(|| cell - self.cols),
+                (y + 1 < self.rows).then(|| cell + self.cols),
+            ];
+
+            for next in neighbours.into_iter().flatten() {
+                if !visited[next] && self.cells[next].is_none() {
+                    visited[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        self.cells
+            .iter_mut()
+            .zip(visited)
+            .filter(|(cell, visited)| cell.is_none() && !visited)
+            .for_each(|(cell, _)| *cell = Some(ObstacleKind::Rock));
+    }
+
+    /// Merges the cells into rectangles to keep the number of obstacles low
+    fn obstacles(&self) -> Vec<Obstacle> {
+        let mut used = vec![false; self.cells.len()];
+        let mut obstacles = vec![];
+        for i in 0..self.cells.len() {
+            let Some(kind) = self.cells[i].filter(|_| !used[i]) else {
+                continue;
+            };
+
+            let (x, y) = (i % self.cols, i / self.cols);
+            let same = |idx: usize| !used[idx] && self.cells[idx] == Some(kind);
+
+            let mut w = 1;
+            while x + w < self.cols && same(i + w) {
+                w += 1;
+            }
+
+            let mut h = 1;
+            while y + h < self.rows && (0..w).all(|dx| same(i + h * self.cols + dx)) {
+                h += 1;
+            }
+
+            for dy in 0..h {
+                for dx in 0..w {
+                    used[i + dy * self.cols + dx] = true;
+                }
+            }
+
+            obstacles.push(Obstacle {
+                kind,
+                pos: (x as f32 * CELL_SIZE, y as f32 * CELL_SIZE),
+                size: (w as f32 * CELL_SIZE, h as f32 * CELL_SIZE),
+            });
+        }
+
+        obstacles
+    }
+}
+
+/// Value noise, random values on a lattice smoothly interpolated between them
+struct Noise {
+    scale: usize,
+    cols: usize,
+    values: Vec<f32>,
+}
+
+impl Noise {
+    fn new(cols: usize, rows: usize, scale: usize, rng: &mut Rng) -> Self {
+        let cols = cols / scale + 2;
+        let rows = rows / scale + 2;
+        let values = (0..cols * rows).map(|_| rng.range(0.0..1.0)).collect();
+        Self {
+            scale,
+            cols,
+            values,
+        }
+    }
+
+    fn sample(&self, x: usize, y: usize) -> f32 {
+        let (cx, cy) = (x / self.scale, y / self.scale);
+        let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+        let tx = smooth((x % self.scale) as f32 / self.scale as f32);
+        let ty = smooth((y % self.scale) as f32 / self.scale as f32);
+
+        let value = |x: usize, y: usize| self.values[y * self.cols + x];
+        let top = value(cx, cy) + (value(cx + 1, cy) - value(cx, cy)) * tx;
+        let bottom = value(cx, cy + 1) + (value(cx + 1, cy + 1) - value(cx, cy + 1)) * tx;
+        top + (bottom - top) * ty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grid of the generated map rebuilt from its obstacles
+    fn grid_of(map: &MapDef) -> Grid {
+        let cols = (map.size.0 / CELL_SIZE) as usize;
+        let rows = (map.size.1 / CELL_SIZE) as usize;
+        let mut grid = Grid::new(cols, rows);
+        for o in &map.obstacles {
+            let (x, y) = (
+                (o.pos.0 / CELL_SIZE) as usize,
+                (o.pos.1 / CELL_SIZE) as usize,
+            );
+            let (w, h) = (
+                (o.size.0 / CELL_SIZE) as usize,
+                (o.size.1 / CELL_SIZE) as usize,
+            );
+            for dy in 0..h {
+                for dx in 0..w {
+                    grid.cells[(y + dy) * cols + x + dx] = Some(o.kind);
+                }
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn same_seed_same_map() {
+        let a = ron::to_string(&generate(7)).unwrap();
+        let b = ron::to_string(&generate(7)).unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, ron::to_string(&generate(8)).unwrap());
+    }
+
+    #[test]
+    fn every_free_cell_is_reachable_from_the_start() {
+        for seed in 0..50 {
+            let map = generate(seed);
+            let mut grid = grid_of(&map);
+            let center = vec2(map.size.0, map.size.1) * 0.5;
+            let start = grid.cell_at(center.round());
+
+            let places = std::iter::once(start)
+                .chain(map.groups.iter().map(|g| grid.cell_at(g.pos())))
+                .chain(map.spawn_points.iter().map(|p| grid.cell_at(p.pos())))
+                .collect::<Vec<_>>();
+            assert!(
+                places.iter().all(|cell| grid.cells[*cell].is_none()),
+                "seed {seed}: the start or some souls are inside an obstacle"
+            );
+
+            // filling the unreachable cells again must not change anything
+            let obstacles = grid.cells.clone();
+            grid.fill_unreachable(start);
+            assert_eq!(
+                grid.cells, obstacles,
+                "seed {seed}: some cells are isolated"
+            );
+        }
+    }
+}
@@ -1,7 +1,9 @@
+use crate::abilities::Ability;
 use rustc_hash::FxHashMap;
-use strum_macros::EnumIter;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::sync::OnceLock;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deserialize)]
 pub struct Params {
     pub sacred_radius: f32,
     pub karma_change_rate: f32,
@@ -13,144 +15,490 @@ pub struct Params {
     // pub eternals_radius: f32,
     pub slow_spawn_time: f32,
     pub block_spawn_souls: usize,
+
+    // steering weights of the souls following the guide
+    #[serde(default = "default_flock_separation")]
+    pub flock_separation: f32,
+    #[serde(default = "default_flock_alignment")]
+    pub flock_alignment: f32,
+    #[serde(default = "default_flock_cohesion")]
+    pub flock_cohesion: f32,
+    #[serde(default = "default_flock_seek")]
+    pub flock_seek: f32,
+    /// Distance to other followers to be considered part of the flock
+    #[serde(default = "default_flock_radius")]
+    pub flock_radius: f32,
 }
 
-pub const PARAMS_START: Params = Params {
-    sacred_radius: 50.0,
-    karma_change_rate: 0.1,
-    karma_expire_rate: 0.015,
-    energy_time: 2.0,
-    energy_amount: 1,
-    following_speed: 85.0,
-    eternals: false,
-    slow_spawn_time: 0.0,
-    block_spawn_souls: 0,
-};
-
-pub const PARAMS_END: Params = Params {
-    sacred_radius: 130.0,
-    karma_change_rate: 0.3,
-    karma_expire_rate: 0.005,
-    energy_time: 1.0,
-    energy_amount: 20,
-    following_speed: 150.0,
-    eternals: true,
-    slow_spawn_time: 20.0,
-    block_spawn_souls: 18,
-};
-
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, EnumIter)]
-#[repr(u8)]
-pub enum Blessing {
-    CircleOfGrace,
-    KarmaAmplification,
-    KarmaPersistence,
-    SpiritualFocus,
-    AscendantEnergy,
-    Devotion,
-    GracefulPause,
-    SpiritualGate,
-    EternalGrace,
+fn default_flock_separation() -> f32 {
+    1.5
 }
 
-impl Blessing {
-    pub fn levels(&self) -> u8 {
-        match self {
-            Blessing::EternalGrace => 1,
-            _ => 10,
+fn default_flock_alignment() -> f32 {
+    0.4
+}
+
+fn default_flock_cohesion() -> f32 {
+    0.2
+}
+
+fn default_flock_seek() -> f32 {
+    1.0
+}
+
+fn default_flock_radius() -> f32 {
+    24.0
+}
+
+/// Params that can be modified by blessings
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum Param {
+    SacredRadius,
+    KarmaChangeRate,
+    KarmaExpireRate,
+    EnergyTime,
+    EnergyAmount,
+    FollowingSpeed,
+    Eternals,
+    SlowSpawnTime,
+    BlockSpawnSouls,
+}
+
+impl Param {
+    /// Params that also change how the second player guides the shadows in versus mode
+    pub fn affects_rival(&self) -> bool {
+        matches!(
+            self,
+            Param::SacredRadius
+                | Param::KarmaChangeRate
+                | Param::EnergyTime
+                | Param::EnergyAmount
+                | Param::FollowingSpeed
+        )
+    }
+}
+
+impl Params {
+    /// Adds the difference between `start` and `end` for the param scaled by `t`
+    fn add_lerp(&mut self, param: Param, start: &Params, end: &Params, t: f32) {
+        let lerp = |a: f32, b: f32| (b - a) * t;
+        let lerp_int = |a: i64, b: i64| ((b - a) as f32 * t).floor() as i64;
+        match param {
+            Param::SacredRadius => {
+                self.sacred_radius += lerp(start.sacred_radius, end.sacred_radius)
+            }
+            Param::KarmaChangeRate => {
+                self.karma_change_rate += lerp(start.karma_change_rate, end.karma_change_rate)
+            }
+            Param::KarmaExpireRate => {
+                self.karma_expire_rate += lerp(start.karma_expire_rate, end.karma_expire_rate)
+            }
+            Param::EnergyTime => self.energy_time += lerp(start.energy_time, end.energy_time),
+            Param::EnergyAmount => {
+                let diff = lerp_int(start.energy_amount as _, end.energy_amount as _);
+                self.energy_amount = (self.energy_amount as i64 + diff).max(0) as _;
+            }
+            Param::FollowingSpeed => {
+                self.following_speed += lerp(start.following_speed, end.following_speed)
+            }
+            Param::Eternals => {
+                if t > 0.0 {
+                    self.eternals = end.eternals;
+                }
+            }
+            Param::SlowSpawnTime => {
+                self.slow_spawn_time += lerp(start.slow_spawn_time, end.slow_spawn_time)
+            }
+            Param::BlockSpawnSouls => {
+                let diff = lerp_int(start.block_spawn_souls as _, end.block_spawn_souls as _);
+                self.block_spawn_souls = (self.block_spawn_souls as i64 + diff).max(0) as _;
+            }
         }
     }
+}
 
-    pub fn price(&self, level: u8) -> u64 {
-        let multi = 1.3 + (level as f32 / 12.0);
-        let base: f32 = match self {
-            Blessing::CircleOfGrace => 10.0,
-            Blessing::KarmaAmplification => 30.0,
-            Blessing::KarmaPersistence => 80.0,
-            Blessing::SpiritualFocus => 150.0,
-            Blessing::AscendantEnergy => 300.0,
-            Blessing::Devotion => 550.0,
-            Blessing::GracefulPause => 1000.0,
-            Blessing::SpiritualGate => 1800.0,
-            Blessing::EternalGrace => 2590.0,
-        };
-
-        (base.powf(multi)) as _
-    }
-
-    pub fn require(&self) -> Option<(u8, Blessing)> {
-        Some(match self {
-            Blessing::CircleOfGrace => return None,
-            Blessing::KarmaAmplification => (4, Blessing::CircleOfGrace),
-            Blessing::KarmaPersistence => (3, Blessing::KarmaAmplification),
-            Blessing::SpiritualFocus => (3, Blessing::KarmaPersistence),
-            Blessing::AscendantEnergy => (2, Blessing::SpiritualFocus),
-            Blessing::Devotion => (2, Blessing::AscendantEnergy),
-            Blessing::GracefulPause => (1, Blessing::Devotion),
-            Blessing::SpiritualGate => (1, Blessing::GracefulPause),
-            Blessing::EternalGrace => (1, Blessing::SpiritualGate),
-        })
+const BLESSINGS_FILE: &str = "assets/blessings.ron";
+static BLESSING_DEFS: OnceLock<Vec<BlessingDef>> = OnceLock::new();
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum PriceCurve {
+    /// base ^ (exponent + level * per_level)
+    Power {
+        base: f32,
+        exponent: f32,
+        per_level: f32,
+    },
+    /// Explicit price for each level
+    Table(Vec<u64>),
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum Effect {
+    /// Interpolates the param from its start value to its end value with the level
+    Lerp(Param),
+    /// Sets the param to its end value once the blessing is unlocked
+    Set(Param),
+    /// Makes the ability available once the blessing is unlocked
+    Ability(Ability),
+}
+
+/// Requirement as it's written in the definitions file
+#[derive(Clone, Debug, Deserialize)]
+pub enum Requirement {
+    /// Blessing id and the minimum level needed
+    Level(String, u8),
+    /// At least one of the requirements must be met
+    AnyOf(Vec<Requirement>),
+    /// All the requirements must be met
+    AllOf(Vec<Requirement>),
+}
+
+/// Requirement resolved to blessings
+#[derive(Clone, Debug)]
+pub enum Prerequisite {
+    Level(Blessing, u8),
+    AnyOf(Vec<Prerequisite>),
+    AllOf(Vec<Prerequisite>),
+}
+
+impl Prerequisite {
+    pub fn is_met(&self, blessings: &Blessings) -> bool {
+        match self {
+            Prerequisite::Level(b, lvl) => blessings.level(b) >= *lvl,
+            Prerequisite::AnyOf(list) => list.iter().any(|p| p.is_met(blessings)),
+            Prerequisite::AllOf(list) => list.iter().all(|p| p.is_met(blessings)),
+        }
     }
 
-    pub fn info(&self) -> (&str, &str) {
+    /// Every blessing mentioned, even the optional ones
+    pub fn blessings(&self) -> Vec<Blessing> {
         match self {
-            Blessing::CircleOfGrace => {
-                let name = "Circle of Grace";
-                let desc = "Increases the sacred zone radius. Luminals follow this zone, converting nearby souls.";
-                (name, desc)
+            Prerequisite::Level(b, _) => vec![*b],
+            Prerequisite::AnyOf(list) | Prerequisite::AllOf(list) => {
+                list.iter().flat_map(|p| p.blessings()).collect()
             }
-            Blessing::KarmaAmplification => {
-                let name = "Karma Amplification";
-                let desc = "Increases the effectiveness of prayers, converting souls faster.";
-                (name, desc)
-            }
-            Blessing::KarmaPersistence => {
-                let name = "Karma Persistence";
-                let desc = "Slows corruption, making souls more resistant to it.";
-                (name, desc)
-            }
-            Blessing::SpiritualFocus => {
-                let name = "Spiritual Focus";
-                let desc = "Increases the focus of prayers, generating spiritual energy faster.";
-                (name, desc)
+        }
+    }
+}
+
+/// Why a blessing can't be unlocked yet
+#[derive(Clone, Debug, Default)]
+pub struct Locked {
+    pub missing: Vec<Prerequisite>,
+    pub excluded_by: Vec<Blessing>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlessingDef {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub icon: (u32, u32),
+    pub max_level: u8,
+    #[serde(default)]
+    pub initial_level: u8,
+    pub price: PriceCurve,
+    #[serde(default)]
+    pub requires: Vec<Requirement>,
+    /// Blessings from other branches that can't be unlocked together with this one
+    #[serde(default)]
+    pub excludes: Vec<String>,
+    #[serde(default)]
+    pub effects: Vec<Effect>,
+
+    // resolved once the file is loaded
+    #[serde(skip)]
+    prerequisites: Vec<Prerequisite>,
+    #[serde(skip)]
+    exclusive: Vec<Blessing>,
+}
+
+/// Loads and checks the blessing definitions, call it before using any blessing
+/// so a broken file is reported as an error like the other data files
+pub fn load_blessings() -> Result<&'static [BlessingDef], String> {
+    if let Some(defs) = BLESSING_DEFS.get() {
+        return Ok(defs);
+    }
+
+    let defs =
+        read_blessing_defs().map_err(|e| format!("Error loading '{BLESSINGS_FILE}': {e}"))?;
+    Ok(BLESSING_DEFS.get_or_init(|| defs))
+}
+
+/// Blessing definitions, loaded the first time they're used if `load_blessings` wasn't called
+pub fn blessing_defs() -> &'static [BlessingDef] {
+    load_blessings().unwrap_or_else(|e| panic!("{e}"))
+}
+
+fn read_blessing_defs() -> Result<Vec<BlessingDef>, String> {
+    // read it from disk when possible, so designers can tweak it without compiling again
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(s) = std::fs::read_to_string(BLESSINGS_FILE) {
+        return parse_blessing_defs(&s);
+    }
+
+    parse_blessing_defs(include_str!("../assets/blessings.ron"))
+}
+
+pub fn parse_blessing_defs(s: &str) -> Result<Vec<BlessingDef>, String> {
+    let mut defs: Vec<BlessingDef> = ron::from_str(s).map_err(|e| e.to_string())?;
+    if defs.len() > u8::MAX as usize {
+        return Err(format!("Too many blessings, max is {}", u8::MAX));
+    }
+
+    let index_of = |defs: &[BlessingDef], id: &str| defs.iter().position(|d| d.id == id);
+
+    for (i, def) in defs.iter().enumerate() {
+        if index_of(&defs, &def.id) != Some(i) {
+            return Err(format!("Blessing '{}' is defined twice", def.id));
+        }
+
+        if let Some(other) = defs[..i].iter().find(|d| d.icon == def.icon) {
+            return Err(format!(
+                "Blessing '{}' uses the same icon as '{}'",
+                def.id, other.id
+            ));
+        }
+
+        if def.max_level == 0 {
+            return Err(format!("Blessing '{}' needs a max_level above 0", def.id));
+        }
+
+        if def.initial_level > def.max_level {
+            return Err(format!(
+                "Blessing '{}' has an initial_level above its max_level",
+                def.id
+            ));
+        }
+
+        if let PriceCurve::Table(prices) = &def.price {
+            if prices.len() < def.max_level as usize {
+                return Err(format!(
+                    "Blessing '{}' needs a price for each of its {} levels",
+                    def.id, def.max_level
+                ));
             }
-            Blessing::AscendantEnergy => {
-                let name = "Ascendant Energy";
-                let desc = "Increases the amount of energy generated by each soul.";
-                (name, desc)
+        }
+    }
+
+    let prerequisites = defs
+        .iter()
+        .map(|def| {
+            def.requires
+                .iter()
+                .map(|r| resolve_requirement(&defs, &def.id, r))
+                .collect::<Result<Vec<_>, String>>()
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    // a blessing that needs itself, even through other blessings, could never be unlocked
+    let needs = prerequisites
+        .iter()
+        .map(|list| list.iter().flat_map(|p| p.blessings()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    if let Some(i) = find_cycle(&needs) {
+        return Err(format!(
+            "Blessing '{}' requires itself through the blessings it needs",
+            defs[i].id
+        ));
+    }
+
+    // exclusions work both ways, even if only one of the blessings lists the other
+    let mut exclusive = vec![vec![]; defs.len()];
+    for (i, def) in defs.iter().enumerate() {
+        for id in &def.excludes {
+            let idx = index_of(&defs, id).ok_or_else(|| {
+                format!("Blessing '{}' excludes an unknown blessing '{id}'", def.id)
+            })?;
+
+            if idx == i {
+                return Err(format!("Blessing '{}' can't exclude itself", def.id));
             }
-            Blessing::Devotion => {
-                let name = "Devotion";
-                let desc = "Souls follow the sacred circle with increased speed and dedication.";
-                (name, desc)
+
+            for (a, b) in [(i, idx), (idx, i)] {
+                let b = Blessing(b as _);
+                if !exclusive[a].contains(&b) {
+                    exclusive[a].push(b);
+                }
             }
-            Blessing::GracefulPause => {
-                let name = "Graceful Pause";
-                let desc = "Increases the time between new soul spawns, preventing flooding.";
-                (name, desc)
+        }
+    }
+
+    defs.iter_mut().zip(prerequisites).zip(exclusive).for_each(
+        |((def, prerequisites), exclusive)| {
+            def.prerequisites = prerequisites;
+            def.exclusive = exclusive;
+        },
+    );
+
+    Ok(defs)
+}
+
+/// Index of a blessing that needs itself, `needs` are the blessings each one requires
+fn find_cycle(needs: &[Vec<Blessing>]) -> Option<usize> {
+    // 1 while the blessing is in the current path, 2 once all its needs are checked
+    fn visit(i: usize, needs: &[Vec<Blessing>], state: &mut [u8]) -> Option<usize> {
+        match state[i] {
+            1 => return Some(i),
+            2 => return None,
+            _ => {}
+        }
+
+        state[i] = 1;
+        let cycle = needs[i].iter().find_map(|b| visit(b.index(), needs, state));
+        state[i] = 2;
+        cycle
+    }
+
+    let mut state = vec![0; needs.len()];
+    (0..needs.len()).find_map(|i| visit(i, needs, &mut state))
+}
+
+fn resolve_requirement(
+    defs: &[BlessingDef],
+    owner: &str,
+    requirement: &Requirement,
+) -> Result<Prerequisite, String> {
+    let resolve_list = |list: &[Requirement]| {
+        if list.is_empty() {
+            return Err(format!("Blessing '{owner}' has an empty requirement group"));
+        }
+
+        list.iter()
+            .map(|r| resolve_requirement(defs, owner, r))
+            .collect::<Result<Vec<_>, String>>()
+    };
+
+    Ok(match requirement {
+        Requirement::Level(id, lvl) => {
+            let idx = defs
+                .iter()
+                .position(|d| &d.id == id)
+                .ok_or_else(|| format!("Blessing '{owner}' requires an unknown blessing '{id}'"))?;
+
+            if *id == owner {
+                return Err(format!("Blessing '{owner}' can't require itself"));
             }
-            Blessing::SpiritualGate => {
-                let name = "Spiritual Gate";
-                let desc = "Reduces the number of souls entering the realm per wave.";
-                (name, desc)
+
+            if *lvl > defs[idx].max_level {
+                return Err(format!(
+                    "Blessing '{owner}' requires '{id}' at level {lvl} but its max_level is {}",
+                    defs[idx].max_level
+                ));
             }
-            Blessing::EternalGrace => {
-                let name = "Eternal Grace";
-                let desc = "Luminals can become incorruptible, converting nearby souls without the need for the sacred circle.";
-                (name, desc)
+
+            Prerequisite::Level(Blessing(idx as _), *lvl)
+        }
+        Requirement::AnyOf(list) => Prerequisite::AnyOf(resolve_list(list)?),
+        Requirement::AllOf(list) => Prerequisite::AllOf(resolve_list(list)?),
+    })
+}
+
+/// Index of a blessing in the definitions file
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Blessing(u8);
+
+impl Blessing {
+    pub fn all() -> impl Iterator<Item = Blessing> {
+        (0..blessing_defs().len()).map(|i| Blessing(i as _))
+    }
+
+    pub fn from_id(id: &str) -> Option<Blessing> {
+        blessing_defs()
+            .iter()
+            .position(|d| d.id == id)
+            .map(|i| Blessing(i as _))
+    }
+
+    pub fn index(&self) -> usize {
+        self.0 as _
+    }
+
+    pub fn def(&self) -> &'static BlessingDef {
+        &blessing_defs()[self.index()]
+    }
+
+    pub fn levels(&self) -> u8 {
+        self.def().max_level
+    }
+
+    pub fn price(&self, level: u8) -> u64 {
+        match &self.def().price {
+            PriceCurve::Power {
+                base,
+                exponent,
+                per_level,
+            } => base.powf(exponent + level as f32 * per_level) as _,
+            PriceCurve::Table(prices) => {
+                let idx = (level as usize).min(prices.len().saturating_sub(1));
+                prices.get(idx).cloned().unwrap_or(0)
             }
         }
     }
+
+    pub fn require(&self) -> &'static [Prerequisite] {
+        &self.def().prerequisites
+    }
+
+    /// Blessings that can't be unlocked together with this one
+    pub fn exclusive(&self) -> &'static [Blessing] {
+        &self.def().exclusive
+    }
+
+    /// Ability unlocked by the blessing, if any
+    pub fn ability(&self) -> Option<Ability> {
+        self.def().effects.iter().find_map(|e| match e {
+            Effect::Ability(a) => Some(*a),
+            _ => None,
+        })
+    }
+
+    /// Blessings that do something for the second player in versus mode,
+    /// abilities are cast with the mouse so they're only for the first player
+    pub fn is_for_rival(&self) -> bool {
+        self.def().effects.iter().all(|e| match e {
+            Effect::Lerp(p) | Effect::Set(p) => p.affects_rival(),
+            Effect::Ability(_) => false,
+        })
+    }
+
+    pub fn info(&self) -> (&'static str, &'static str) {
+        let def = self.def();
+        (&def.name, &def.description)
+    }
+}
+
+// blessings are stored by id in saves and replays, so they still work if the list is reordered
+impl Serialize for Blessing {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.def().id)
+    }
+}
+
+impl<'de> Deserialize<'de> for Blessing {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        Blessing::from_id(&id)
+            .ok_or_else(|| serde::de::Error::custom(format!("Unknown blessing '{id}'")))
+    }
 }
 
 pub struct Blessings {
     list: FxHashMap<Blessing, u8>,
 }
 
+impl Default for Blessings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Blessings {
     pub fn new() -> Self {
-        let mut list = FxHashMap::default();
-        list.insert(Blessing::CircleOfGrace, 1);
+        let list = Blessing::all()
+            .filter(|b| b.def().initial_level > 0)
+            .map(|b| (b, b.def().initial_level))
+            .collect();
         Self { list }
     }
 
@@ -158,90 +506,248 @@ impl Blessings {
         self.list.get(blessing).cloned().unwrap_or(0)
     }
 
-    pub fn can_unlock(&self, blessing: Blessing) -> bool {
-        match blessing.require() {
-            Some((lvl, b)) => self.list.get(&b).map_or(false, |v| *v >= lvl),
-            None => true,
+    pub fn set_level(&mut self, blessing: Blessing, level: u8) {
+        self.list.insert(blessing, level.min(blessing.levels()));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Blessing, u8)> + '_ {
+        self.list.iter().map(|(b, lvl)| (*b, *lvl))
+    }
+
+    pub fn can_unlock(&self, blessing: Blessing) -> Result<(), Locked> {
+        let missing = blessing
+            .require()
+            .iter()
+            .filter(|p| !p.is_met(self))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let excluded_by = blessing
+            .exclusive()
+            .iter()
+            .filter(|b| self.level(b) > 0)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if missing.is_empty() && excluded_by.is_empty() {
+            Ok(())
+        } else {
+            Err(Locked {
+                missing,
+                excluded_by,
+            })
         }
     }
 
+    /// Adds a level to the blessing, false if it's locked or already at its max level
     pub fn unlock(&mut self, blessing: Blessing) -> bool {
-        if !self.can_unlock(blessing) {
+        let lvl = self.level(&blessing);
+        if lvl >= blessing.levels() || self.can_unlock(blessing).is_err() {
             return false;
         }
 
-        let lvl = self.list.entry(blessing).or_insert(0);
-        if *lvl < blessing.levels() {
-            *lvl += 1;
-        }
+        self.list.insert(blessing, lvl + 1);
         true
     }
 
-    pub fn params(&self) -> Params {
-        let mut params = PARAMS_START;
-
-        if let Some(lvl) = self.list.get(&Blessing::CircleOfGrace) {
-            let max = Blessing::CircleOfGrace.levels() as f32;
-            let n = *lvl as f32 / max;
-            let diff = PARAMS_END.sacred_radius - PARAMS_START.sacred_radius;
-            params.sacred_radius += diff * n;
+    /// Removes one level of the blessing and any level of other blessings that
+    /// depended on it, returns the energy spent on all the removed levels
+    pub fn remove_level(&mut self, blessing: Blessing) -> Option<u64> {
+        let lvl = self.level(&blessing);
+        // initial levels are free, so there is nothing to give back
+        if lvl <= blessing.def().initial_level {
+            return None;
         }
 
-        if let Some(lvl) = self.list.get(&Blessing::KarmaAmplification) {
-            let max = Blessing::KarmaAmplification.levels() as f32;
-            let n = *lvl as f32 / max;
-            let diff = PARAMS_END.karma_change_rate - PARAMS_START.karma_change_rate;
-            params.karma_change_rate += diff * n;
-        }
+        self.list.insert(blessing, lvl - 1);
+        let mut spent = blessing.price(lvl - 1);
 
-        if let Some(lvl) = self.list.get(&Blessing::KarmaPersistence) {
-            let max = Blessing::KarmaPersistence.levels() as f32;
-            let n = *lvl as f32 / max;
-            let diff = PARAMS_END.karma_expire_rate - PARAMS_START.karma_expire_rate;
-            params.karma_expire_rate += diff * n;
-        }
+        // removing a blessing can invalidate others, and those can invalidate others too
+        loop {
+            let invalid = Blessing::all().find(|b| {
+                self.level(b) > b.def().initial_level && b.require().iter().any(|p| !p.is_met(self))
+            });
 
-        if let Some(lvl) = self.list.get(&Blessing::SpiritualFocus) {
-            let max = Blessing::SpiritualFocus.levels() as f32;
-            let n = *lvl as f32 / max;
-            let diff = PARAMS_END.energy_time - PARAMS_START.energy_time;
-            params.energy_time += diff * n;
-        }
+            let Some(b) = invalid else {
+                break;
+            };
 
-        if let Some(lvl) = self.list.get(&Blessing::AscendantEnergy) {
-            let max = Blessing::AscendantEnergy.levels() as f32;
-            let n = *lvl as f32 / max;
-            let diff = PARAMS_END.energy_amount - PARAMS_START.energy_amount;
-            params.energy_amount += (diff as f32 * n).floor() as u64;
+            let lvl = self.level(&b);
+            let initial = b.def().initial_level;
+            spent += (initial..lvl).map(|l| b.price(l)).sum::<u64>();
+            self.list.insert(b, initial);
         }
 
-        if let Some(lvl) = self.list.get(&Blessing::Devotion) {
-            let max = Blessing::Devotion.levels() as f32;
-            let n = *lvl as f32 / max;
-            let diff = PARAMS_END.following_speed - PARAMS_START.following_speed;
-            params.following_speed += diff * n;
-        }
+        Some(spent)
+    }
 
-        if let Some(lvl) = self.list.get(&Blessing::GracefulPause) {
-            let max = Blessing::GracefulPause.levels() as f32;
-            let n = *lvl as f32 / max;
-            let diff = PARAMS_END.slow_spawn_time - PARAMS_START.slow_spawn_time;
-            params.slow_spawn_time += diff * n;
-        }
+    pub fn has_ability(&self, ability: Ability) -> bool {
+        self.iter().any(|(b, lvl)| {
+            lvl > 0
+                && b.def()
+                    .effects
+                    .iter()
+                    .any(|e| matches!(e, Effect::Ability(a) if *a == ability))
+        })
+    }
 
-        if let Some(lvl) = self.list.get(&Blessing::SpiritualGate) {
-            let max = Blessing::SpiritualGate.levels() as f32;
-            let n = *lvl as f32 / max;
-            let diff = PARAMS_END.block_spawn_souls - PARAMS_START.block_spawn_souls;
-            params.block_spawn_souls += (diff as f32 * n).floor() as usize;
-        }
+    pub fn params(&self, start: &Params, end: &Params) -> Params {
+        let mut params = *start;
 
-        if let Some(lvl) = self.list.get(&Blessing::EternalGrace) {
-            if *lvl > 0 {
-                params.eternals = true;
+        // follow the definitions order to always add the values in the same order
+        Blessing::all().for_each(|b| {
+            let lvl = self.level(&b);
+            if lvl == 0 {
+                return;
             }
-        }
+
+            let def = b.def();
+            let n = lvl as f32 / def.max_level as f32;
+            def.effects.iter().for_each(|effect| match effect {
+                Effect::Lerp(p) => params.add_lerp(*p, start, end, n),
+                Effect::Set(p) => params.add_lerp(*p, start, end, 1.0),
+                Effect::Ability(_) => {}
+            });
+        });
 
         params
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blessing(id: &str) -> Blessing {
+        Blessing::from_id(id).unwrap()
+    }
+
+    fn def(id: &str, icon: u32, requires: &str) -> String {
+        format!(
+            r#"(
+                id: "{id}",
+                name: "{id}",
+                description: "",
+                icon: ({icon}, 0),
+                max_level: 3,
+                price: Table([1, 2, 3]),
+                requires: [{requires}],
+            ),"#
+        )
+    }
+
+    #[test]
+    fn missing_requirements_are_reported() {
+        let mut blessings = Blessings::new();
+        let amplification = blessing("karma_amplification");
+
+        let locked = blessings.can_unlock(amplification).unwrap_err();
+        assert!(locked.excluded_by.is_empty());
+        assert!(matches!(
+            locked.missing[..],
+            [Prerequisite::Level(b, 4)] if b == blessing("circle_of_grace")
+        ));
+
+        blessings.set_level(blessing("circle_of_grace"), 4);
+        assert!(blessings.can_unlock(amplification).is_ok());
+    }
+
+    #[test]
+    fn any_of_needs_one_of_the_blessings() {
+        let mut blessings = Blessings::new();
+        let eternal_grace = blessing("eternal_grace");
+        blessings.set_level(blessing("devotion"), 2);
+
+        let locked = blessings.can_unlock(eternal_grace).unwrap_err();
+        assert!(matches!(locked.missing[..], [Prerequisite::AnyOf(_)]));
+
+        blessings.set_level(blessing("spiritual_gate"), 1);
+        assert!(blessings.can_unlock(eternal_grace).is_ok());
+    }
+
+    #[test]
+    fn exclusions_work_both_ways() {
+        let mut blessings = Blessings::new();
+        let pause = blessing("graceful_pause");
+        let gate = blessing("spiritual_gate");
+        blessings.set_level(blessing("devotion"), 1);
+        assert!(blessings.unlock(pause));
+
+        let locked = blessings.can_unlock(gate).unwrap_err();
+        assert!(locked.missing.is_empty());
+        assert_eq!(locked.excluded_by, vec![pause]);
+
+        blessings.set_level(pause, 0);
+        blessings.set_level(gate, 1);
+        assert_eq!(
+            blessings.can_unlock(pause).unwrap_err().excluded_by,
+            vec![gate]
+        );
+    }
+
+    #[test]
+    fn unlock_stops_at_the_max_level() {
+        let mut blessings = Blessings::new();
+        let eternal_grace = blessing("eternal_grace");
+        blessings.set_level(blessing("devotion"), 2);
+        blessings.set_level(blessing("graceful_pause"), 1);
+
+        assert!(blessings.unlock(eternal_grace));
+        assert!(!blessings.unlock(eternal_grace));
+        assert_eq!(blessings.level(&eternal_grace), 1);
+    }
+
+    #[test]
+    fn removing_a_level_resets_the_blessings_that_needed_it() {
+        let circle = blessing("circle_of_grace");
+        let chain = [
+            "karma_amplification",
+            "karma_persistence",
+            "spiritual_focus",
+        ]
+        .map(blessing);
+
+        let mut blessings = Blessings::new();
+        blessings.set_level(circle, 5);
+        blessings.set_level(chain[0], 3);
+        blessings.set_level(chain[1], 3);
+        blessings.set_level(chain[2], 1);
+
+        // karma_amplification only needs the level 4
+        assert_eq!(blessings.remove_level(circle), Some(circle.price(4)));
+        assert_eq!(blessings.level(&chain[0]), 3);
+
+        let refund = circle.price(3)
+            + (0..3).map(|l| chain[0].price(l)).sum::<u64>()
+            + (0..3).map(|l| chain[1].price(l)).sum::<u64>()
+            + chain[2].price(0);
+        assert_eq!(blessings.remove_level(circle), Some(refund));
+        assert_eq!(blessings.level(&circle), 3);
+        chain
+            .iter()
+            .for_each(|b| assert_eq!(blessings.level(b), b.def().initial_level));
+
+        // the initial level is free, so it can't be removed
+        blessings.set_level(circle, circle.def().initial_level);
+        assert_eq!(blessings.remove_level(circle), None);
+    }
+
+    #[test]
+    fn requirement_cycles_are_rejected() {
+        let s = format!(
+            "[{}{}{}]",
+            def("a", 0, r#"Level("c", 1)"#),
+            def("b", 1, r#"Level("a", 1)"#),
+            def("c", 2, r#"AnyOf([Level("b", 1)])"#),
+        );
+        let err = parse_blessing_defs(&s).unwrap_err();
+        assert!(err.contains("requires itself"), "{err}");
+
+        let s = format!("[{}]", def("a", 0, r#"Level("a", 1)"#));
+        let err = parse_blessing_defs(&s).unwrap_err();
+        assert!(err.contains("can't require itself"), "{err}");
+
+        let s = format!("[{}{}]", def("a", 0, ""), def("b", 1, r#"Level("a", 1)"#));
+        assert!(parse_blessing_defs(&s).is_ok());
+    }
+}
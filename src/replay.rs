@@ -0,0 +1,339 @@
+use crate::abilities::Ability;
+use crate::campaign::load_level;
+use crate::config::{load_preset, DEFAULT_PRESET};
+use crate::daily::Daily;
+use crate::map::{load_map, DEFAULT_MAP};
+use crate::params::Blessing;
+use crate::sim::{Input, Simulation};
+use crate::versus::RivalInput;
+use glam::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+pub const REPLAY_VERSION: u32 = 1;
+pub const LAST_REPLAY_FILE: &str = "last_run.replay";
+
+/// The simulation advances 1 / tick_rate seconds each tick, so it must be a positive number
+pub fn check_tick_rate(tick_rate: f32) -> Result<(), String> {
+    if !tick_rate.is_finite() || tick_rate <= 0.0 {
+        return Err(format!(
+            "Invalid tick rate '{tick_rate}', it must be a number above 0"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Input of a single tick as it's stored in the replay file
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct TickInput {
+    guide: (f32, f32),
+    guiding: bool,
+    camera: (i8, i8),
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    unlock: Option<Blessing>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    respec: Option<Blessing>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ability: Option<Ability>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rival: Option<RivalTickInput>,
+}
+
+/// Input of the second player in versus mode
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct RivalTickInput {
+    dir: (i8, i8),
+    guiding: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    unlock: Option<Blessing>,
+}
+
+impl From<&Input> for TickInput {
+    fn from(input: &Input) -> Self {
+        Self {
+            guide: (input.guide_pos.x, input.guide_pos.y),
+            guiding: input.is_guiding,
+            camera: (input.camera_move.x as _, input.camera_move.y as _),
+            unlock: input.unlock,
+            respec: input.respec,
+            ability: input.ability,
+            rival: (input.rival.move_dir != Vec2::ZERO
+                || input.rival.is_guiding
+                || input.rival.unlock.is_some())
+            .then_some(RivalTickInput {
+                dir: (input.rival.move_dir.x as _, input.rival.move_dir.y as _),
+                guiding: input.rival.is_guiding,
+                unlock: input.rival.unlock,
+            }),
+        }
+    }
+}
+
+impl From<&TickInput> for Input {
+    fn from(input: &TickInput) -> Self {
+        Self {
+            guide_pos: vec2(input.guide.0, input.guide.1),
+            is_guiding: input.guiding,
+            camera_move: vec2(input.camera.0 as _, input.camera.1 as _),
+            unlock: input.unlock,
+            respec: input.respec,
+            ability: input.ability,
+            rival: input.rival.map_or(RivalInput::default(), |r| RivalInput {
+                move_dir: vec2(r.dir.0 as _, r.dir.1 as _),
+                is_guiding: r.guiding,
+                unlock: r.unlock,
+            }),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct ReplayResult {
+    pub ticks: u32,
+    pub good_progress: f32,
+    pub bad_progress: f32,
+}
+
+/// Seed and per-tick inputs of a full run, the inputs are run-length encoded
+/// because most of the time the player is doing the same thing for many ticks
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub tick_rate: f32,
+    #[serde(default = "default_difficulty")]
+    pub difficulty: String,
+    #[serde(default = "default_map")]
+    pub map: String,
+    #[serde(default)]
+    pub versus: bool,
+    #[serde(default)]
+    pub endless: bool,
+    /// Campaign level played
+    #[serde(default)]
+    pub level: Option<String>,
+    /// Day of the daily challenge played
+    #[serde(default)]
+    pub daily: Option<u64>,
+    inputs: Vec<(u32, TickInput)>,
+    #[serde(default)]
+    pub result: Option<ReplayResult>,
+}
+
+impl Replay {
+    /// Only for regular games, the other modes are set after creating it
+    pub fn new(seed: u64, tick_rate: f32, difficulty: &str, map: &str) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            seed,
+            tick_rate,
+            difficulty: difficulty.to_string(),
+            map: map.to_string(),
+            versus: false,
+            endless: false,
+            level: None,
+            daily: None,
+            inputs: vec![],
+            result: None,
+        }
+    }
+
+    pub fn record(&mut self, input: &Input) {
+        let input = TickInput::from(input);
+        match self.inputs.last_mut() {
+            Some((n, last)) if *last == input => *n += 1,
+            _ => self.inputs.push((1, input)),
+        }
+    }
+
+    /// Stores the final state of the run to check it against future playbacks
+    pub fn finish(&mut self, sim: &Simulation) {
+        self.result = Some(ReplayResult {
+            ticks: self.ticks(),
+            good_progress: sim.good_progress,
+            bad_progress: sim.bad_progress,
+        });
+    }
+
+    pub fn ticks(&self) -> u32 {
+        self.inputs.iter().map(|(n, _)| n).sum()
+    }
+
+    pub fn inputs(&self) -> impl Iterator<Item = Input> + '_ {
+        self.inputs
+            .iter()
+            .flat_map(|(n, input)| (0..*n).map(move |_| Input::from(input)))
+    }
+
+    /// Runs the whole replay without window, useful to check that a run is still reproducible
+    pub fn simulate(&self) -> Result<Simulation, String> {
+        self.run().map(|(sim, _)| sim)
+    }
+
+    /// Runs the replay and checks that it ends the same way it did when it was recorded
+    pub fn verify(&self) -> Result<(), String> {
+        let Some(expected) = self.result else {
+            return Err("The replay has no result to check against".to_string());
+        };
+
+        let (sim, ticks) = self.run()?;
+        if ticks != expected.ticks {
+            return Err(format!(
+                "The replay ran for {ticks} ticks instead of {}",
+                expected.ticks
+            ));
+        }
+
+        if sim.good_progress != expected.good_progress || sim.bad_progress != expected.bad_progress
+        {
+            return Err(format!(
+                "The replay ended with progress {}/{} instead of {}/{}",
+                sim.good_progress, sim.bad_progress, expected.good_progress, expected.bad_progress
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Simulation at the end of the replay and the ticks it took to get there
+    fn run(&self) -> Result<(Simulation, u32), String> {
+        check_tick_rate(self.tick_rate)?;
+        let dt = 1.0 / self.tick_rate;
+        let config = load_preset(&self.difficulty)?;
+        let mut sim = Simulation::new(self.seed, config, load_map(&self.map)?);
+        if self.versus {
+            sim.start_versus();
+        }
+        if self.endless {
+            sim.start_endless();
+        }
+        if let Some(id) = &self.level {
+            sim.start_level(load_level(id)?);
+        }
+        if let Some(day) = self.daily {
+            sim.start_daily(Daily::new(day));
+        }
+        sim.init_spawn();
+        let mut ticks = 0;
+        for input in self.inputs() {
+            sim.update(dt, &input);
+            ticks += 1;
+            if sim.outcome.is_some() {
+                break;
+            }
+        }
+
+        Ok((sim, ticks))
+    }
+
+    pub fn to_ron(&self) -> Result<String, String> {
+        ron::to_string(self).map_err(|e| e.to_string())
+    }
+
+    pub fn from_ron(s: &str) -> Result<Self, String> {
+        let replay: Self = ron::from_str(s).map_err(|e| e.to_string())?;
+        if replay.version > REPLAY_VERSION {
+            return Err(format!(
+                "Replay version '{}' is not supported (max '{REPLAY_VERSION}')",
+                replay.version
+            ));
+        }
+
+        check_tick_rate(replay.tick_rate)?;
+        Ok(replay)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, file: &str) -> Result<(), String> {
+        crate::storage::write(file, &self.to_ron()?)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(file: &str) -> Result<Self, String> {
+        Self::from_ron(&crate::storage::read(file)?)
+    }
+}
+
+fn default_difficulty() -> String {
+    DEFAULT_PRESET.to_string()
+}
+
+fn default_map() -> String {
+    DEFAULT_MAP.to_string()
+}
+
+/// Feeds the inputs of a replay tick by tick
+pub struct Playback {
+    pub replay: Replay,
+    run: usize,
+    tick: u32,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            run: 0,
+            tick: 0,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.run >= self.replay.inputs.len()
+    }
+
+    pub fn next_input(&mut self) -> Option<Input> {
+        let (n, input) = self.replay.inputs.get(self.run)?;
+        let input = Input::from(input);
+        self.tick += 1;
+        if self.tick >= *n {
+            self.tick = 0;
+            self.run += 1;
+        }
+
+        Some(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // short run on the default map, if this breaks the simulation is not deterministic anymore
+    // or a change in the rules made the old replays invalid, record it again in that case
+    const MEADOW: &str = include_str!("../tests/replays/meadow.replay");
+
+    #[test]
+    fn replay_is_reproducible() {
+        let replay = Replay::from_ron(MEADOW).unwrap();
+        assert!(replay.result.is_some());
+        assert_eq!(replay.verify(), Ok(()));
+    }
+
+    #[test]
+    fn replay_with_a_different_result_fails() {
+        let mut replay = Replay::from_ron(MEADOW).unwrap();
+        if let Some(result) = &mut replay.result {
+            result.good_progress += 0.01;
+        }
+        assert!(replay.verify().is_err());
+    }
+
+    #[test]
+    fn replay_without_a_valid_tick_rate_is_rejected() {
+        let s = MEADOW.replacen("tick_rate:60.0", "tick_rate:0.0", 1);
+        assert!(Replay::from_ron(&s).is_err());
+    }
+
+    /// Stores the new result of the replays after a change in the rules,
+    /// run it with `cargo test update_replay_results -- --ignored`
+    #[test]
+    #[ignore]
+    fn update_replay_results() {
+        let mut replay = Replay::from_ron(MEADOW).unwrap();
+        let sim = replay.simulate().unwrap();
+        replay.finish(&sim);
+        let file = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/replays/meadow.replay");
+        std::fs::write(file, replay.to_ron().unwrap() + "\n").unwrap();
+    }
+}
@@ -1,11 +1,13 @@
+use ld56::params::blessing_defs;
 use rkit::draw;
 use rkit::draw::Sprite;
 use rkit::gfx::TextureFilter;
 use rkit::math::{vec2, Rect, Vec2};
 
 pub struct Resources {
+    base: Sprite,
     pub souls_icon: Sprite,
-    pub blessings: [Sprite; 9],
+    pub blessings: Vec<Sprite>,
     pub tile: Sprite,
     pub bar: Sprite,
     pub bar_outline: Sprite,
@@ -29,17 +31,13 @@ impl Resources {
 
         let grid_size = Vec2::splat(16.0);
         let souls_icon = base.clone_with_frame(Rect::new(Vec2::ZERO, grid_size));
-        let blessings = [
-            base.clone_with_frame(Rect::new(grid_size * vec2(1.0, 0.0), grid_size)),
-            base.clone_with_frame(Rect::new(grid_size * vec2(2.0, 0.0), grid_size)),
-            base.clone_with_frame(Rect::new(grid_size * vec2(3.0, 0.0), grid_size)),
-            base.clone_with_frame(Rect::new(grid_size * vec2(4.0, 0.0), grid_size)),
-            base.clone_with_frame(Rect::new(grid_size * vec2(6.0, 0.0), grid_size)),
-            base.clone_with_frame(Rect::new(grid_size * vec2(5.0, 0.0), grid_size)),
-            base.clone_with_frame(Rect::new(grid_size * vec2(0.0, 1.0), grid_size)),
-            base.clone_with_frame(Rect::new(grid_size * vec2(1.0, 1.0), grid_size)),
-            base.clone_with_frame(Rect::new(grid_size * vec2(2.0, 1.0), grid_size)),
-        ];
+        let blessings = blessing_defs()
+            .iter()
+            .map(|def| {
+                let (x, y) = def.icon;
+                base.clone_with_frame(Rect::new(grid_size * vec2(x as _, y as _), grid_size))
+            })
+            .collect();
         let neutral = base.clone_with_frame(Rect::new(grid_size * vec2(3.0, 1.0), grid_size));
         let shirt = base.clone_with_frame(Rect::new(grid_size * vec2(4.0, 1.0), grid_size));
         let shadow = base.clone_with_frame(Rect::new(grid_size * vec2(5.0, 1.0), grid_size));
@@ -54,12 +52,13 @@ impl Resources {
             grid_size * vec2(8.0, 1.0),
         ));
 
-        let tile = base.clone_with_frame(Rect::new(grid_size * vec2(0.0, 4.0), grid_size * 5.0));
+        let tile = background_frame(&base, (0, 4));
         let circle = base.clone_with_frame(Rect::new(grid_size * vec2(5.0, 4.0), grid_size * 2.0));
         let karma_circle = base.clone_with_frame(Rect::new(grid_size * vec2(6.0, 8.0), grid_size));
         let karma_white = base.clone_with_frame(Rect::new(grid_size * vec2(6.0, 7.0), grid_size));
 
         Ok(Self {
+            base,
             souls_icon,
             blessings,
             tile,
@@ -75,4 +74,14 @@ impl Resources {
             karma_white,
         })
     }
+
+    /// Each map can use its own background tile
+    pub fn set_background(&mut self, (x, y): (u32, u32)) {
+        self.tile = background_frame(&self.base, (x, y));
+    }
+}
+
+fn background_frame(base: &Sprite, (x, y): (u32, u32)) -> Sprite {
+    let grid_size = Vec2::splat(16.0);
+    base.clone_with_frame(Rect::new(grid_size * vec2(x as _, y as _), grid_size * 5.0))
 }
@@ -0,0 +1,42 @@
+use std::ops::Range;
+
+/// Seedable random generator, the same seed always gives the same sequence
+pub struct Rng {
+    seed: u64,
+    inner: fastrand::Rng,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            inner: fastrand::Rng::with_seed(seed),
+        }
+    }
+
+    /// Restores a generator in the middle of its sequence
+    pub fn with_state(seed: u64, state: u64) -> Self {
+        Self {
+            seed,
+            inner: fastrand::Rng::with_seed(state),
+        }
+    }
+
+    /// Current internal state, to resume it later with `Rng::with_state`
+    pub fn state(&self) -> u64 {
+        self.inner.get_seed()
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn range(&mut self, range: Range<f32>) -> f32 {
+        range.start + self.inner.f32() * (range.end - range.start)
+    }
+
+    /// Random index for a list of `len` elements
+    pub fn index(&mut self, len: usize) -> usize {
+        self.inner.usize(..len)
+    }
+}
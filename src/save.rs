@@ -0,0 +1,279 @@
+use crate::abilities::Ability;
+use crate::campaign::LevelDef;
+use crate::config::{GameConfig, DEFAULT_PRESET};
+use crate::daily::Daily;
+use crate::map::{MapDef, DEFAULT_MAP};
+use crate::params::{Blessing, Blessings};
+use crate::rng::Rng;
+use crate::sim::Simulation;
+use crate::souls::{KarmaConversion, Movement, Soul, VisualData};
+use glam::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+pub const SAVE_VERSION: u32 = 1;
+pub const SAVE_FILE: &str = "savegame.ron";
+
+// Every field added after the first version must have a default value
+// so old save files can still be loaded
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SoulSave {
+    pub id: u64,
+    pub karma: f32,
+    pub pos: (f32, f32),
+    pub energy_timer: f32,
+    pub conversion: KarmaConversion,
+    /// Destination of the wanderers
+    #[serde(default)]
+    pub wander_target: Option<(f32, f32)>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SaveData {
+    pub version: u32,
+    #[serde(default = "default_difficulty")]
+    pub difficulty: String,
+    #[serde(default = "default_map")]
+    pub map: String,
+    pub seed: u64,
+    pub rng_state: u64,
+    pub ids: u64,
+    pub position: (f32, f32),
+    pub souls: Vec<SoulSave>,
+    pub blessings: Vec<(Blessing, u8)>,
+    #[serde(default)]
+    pub cooldowns: Vec<(Ability, f32)>,
+    // position and target
+    #[serde(default)]
+    pub tempter: Option<((f32, f32), (f32, f32))>,
+    // corruption of each zone of the map, in the same order
+    #[serde(default)]
+    pub zones: Vec<(f32, bool)>,
+
+    // spawner
+    pub spawn_time: f32,
+    pub spawn_timer: f32,
+    pub spawn_num: usize,
+    #[serde(default)]
+    pub wave_number: usize,
+
+    // stats
+    pub energy: u64,
+    pub total_energy: u64,
+    pub play_time: f32,
+    #[serde(default)]
+    pub conversions: u64,
+    #[serde(default)]
+    pub endless: bool,
+    /// Campaign level being played
+    #[serde(default)]
+    pub level: Option<String>,
+    // timers and counters of the win and lose conditions, in the same order
+    #[serde(default)]
+    pub conditions: Vec<f32>,
+    /// Day of the daily challenge being played
+    #[serde(default)]
+    pub daily: Option<u64>,
+}
+
+fn default_difficulty() -> String {
+    DEFAULT_PRESET.to_string()
+}
+
+fn default_map() -> String {
+    DEFAULT_MAP.to_string()
+}
+
+impl SaveData {
+    pub fn from_sim(sim: &Simulation) -> Self {
+        let souls = sim
+            .souls
+            .iter()
+            .map(|s| SoulSave {
+                id: s.id,
+                karma: s.karma,
+                pos: (s.pos.x, s.pos.y),
+                energy_timer: s.energy_timer,
+                conversion: s.conversion,
+                wander_target: match s.movement {
+                    Movement::Wander { target } => Some((target.x, target.y)),
+                    Movement::Idle => None,
+                },
+            })
+            .collect();
+
+        Self {
+            version: SAVE_VERSION,
+            difficulty: sim.config.name.clone(),
+            map: sim.map.id.clone(),
+            seed: sim.rng.seed(),
+            rng_state: sim.rng.state(),
+            ids: sim.ids,
+            position: (sim.position.x, sim.position.y),
+            souls,
+            blessings: sim.blessings.iter().collect(),
+            cooldowns: Ability::ALL
+                .into_iter()
+                .map(|a| (a, sim.abilities.cooldown(a)))
+                .filter(|(_, cd)| *cd > 0.0)
+                .collect(),
+            tempter: sim
+                .tempter
+                .as_ref()
+                .map(|t| ((t.pos.x, t.pos.y), (t.target.x, t.target.y))),
+            zones: sim
+                .zones
+                .list
+                .iter()
+                .map(|z| (z.corruption, z.is_corrupted))
+                .collect(),
+            spawn_time: sim.waves.delay,
+            spawn_timer: sim.waves.timer,
+            spawn_num: sim.waves.count,
+            wave_number: sim.waves.number,
+            energy: sim.energy,
+            total_energy: sim.total_energy,
+            play_time: sim.play_time,
+            conversions: sim.conversions,
+            endless: sim.is_endless(),
+            level: sim.level.as_ref().map(|l| l.id.clone()),
+            conditions: {
+                let mut state = vec![];
+                if let Some(win) = &sim.win {
+                    win.save_state(&mut state);
+                }
+                sim.lose.save_state(&mut state);
+                state
+            },
+            daily: sim.daily.as_ref().map(|d| d.day),
+        }
+    }
+
+    /// `config`, `map` and `level` are expected to be the ones named in the save
+    pub fn into_sim(self, config: GameConfig, map: MapDef, level: Option<LevelDef>) -> Simulation {
+        let mut sim = Simulation::new(self.seed, config, map);
+        // the modifiers and the waves of the day go before restoring the rest
+        if let Some(day) = self.daily {
+            sim.start_daily(Daily::new(day));
+        }
+        sim.rng = Rng::with_state(self.seed, self.rng_state);
+        sim.ids = self.ids;
+        sim.position = vec2(self.position.0, self.position.1);
+        sim.prev_position = sim.position;
+
+        let mut blessings = Blessings::new();
+        self.blessings
+            .into_iter()
+            .for_each(|(b, lvl)| blessings.set_level(b, lvl));
+        sim.blessings = blessings;
+        sim.refresh_params();
+
+        self.cooldowns
+            .into_iter()
+            .for_each(|(a, cd)| sim.abilities.set_cooldown(a, cd));
+
+        if let (Some(t), Some((pos, target))) = (&mut sim.tempter, self.tempter) {
+            t.pos = vec2(pos.0, pos.1);
+            t.prev_pos = t.pos;
+            t.target = vec2(target.0, target.1);
+        }
+
+        sim.zones
+            .list
+            .iter_mut()
+            .zip(self.zones)
+            .for_each(|(z, (corruption, is_corrupted))| {
+                z.corruption = corruption;
+                z.is_corrupted = is_corrupted;
+            });
+
+        sim.souls =
+            self.souls
+                .into_iter()
+                .map(|s| {
+                    let pos = vec2(s.pos.0, s.pos.1);
+                    Soul {
+                        id: s.id,
+                        karma: s.karma,
+                        pos,
+                        prev_pos: pos,
+                        vel: Vec2::ZERO,
+                        is_following: false,
+                        is_tempted: false,
+                        energy_timer: s.energy_timer,
+                        movement: s.wander_target.map_or(Movement::Idle, |(x, y)| {
+                            Movement::Wander { target: vec2(x, y) }
+                        }),
+                        // visuals are not part of the game state, so they don't use the sim's rng
+                        visuals: VisualData::new(&mut Rng::new(s.id)),
+                        conversion: s.conversion,
+                    }
+                })
+                .collect();
+
+        sim.waves.delay = self.spawn_time;
+        sim.waves.timer = self.spawn_timer;
+        sim.waves.count = self.spawn_num;
+        sim.waves.number = self.wave_number;
+        sim.waves.skip_to(self.play_time);
+
+        sim.energy = self.energy;
+        sim.total_energy = self.total_energy;
+        sim.play_time = self.play_time;
+        sim.conversions = self.conversions;
+        if self.endless {
+            sim.start_endless();
+        }
+        if let Some(def) = level {
+            sim.start_level(def);
+        }
+
+        let mut state = self.conditions.iter();
+        if let Some(win) = &mut sim.win {
+            win.load_state(&mut state);
+        }
+        sim.lose.load_state(&mut state);
+
+        sim
+    }
+
+    pub fn to_ron(&self) -> Result<String, String> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn from_ron(s: &str) -> Result<Self, String> {
+        #[derive(Deserialize)]
+        struct Header {
+            version: u32,
+        }
+
+        // newer saves only add fields, so they're loaded as long as the rest still parses
+        let data: Self = ron::from_str(s).map_err(|e| match ron::from_str::<Header>(s) {
+            Ok(h) if h.version > SAVE_VERSION => format!(
+                "Save version '{}' is not supported (max '{SAVE_VERSION}'): {e}",
+                h.version
+            ),
+            _ => e.to_string(),
+        })?;
+
+        if data.version > SAVE_VERSION {
+            eprintln!(
+                "Warning: save version '{}' is newer than '{SAVE_VERSION}', some data may be lost",
+                data.version
+            );
+        }
+
+        Ok(data)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self) -> Result<(), String> {
+        crate::storage::write(SAVE_FILE, &self.to_ron()?)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Result<Self, String> {
+        Self::from_ron(&crate::storage::read(SAVE_FILE)?)
+    }
+}
@@ -0,0 +1,941 @@
+use crate::abilities::{Abilities, Ability};
+use crate::campaign::LevelDef;
+use crate::conditions::{
+    Any, Influence, LoseCondition, NoInfluence, ShadowInfluence, Snapshot, WinCondition,
+};
+use crate::config::GameConfig;
+use crate::daily::{Daily, Modifier};
+use crate::endless::Score;
+use crate::map::{MapDef, ZoneKind};
+use crate::params::{Blessing, Blessings, Params};
+use crate::rng::Rng;
+use crate::souls::{KarmaConversion, Movement, Soul, SoulKind, VisualData};
+use crate::tempter::Tempter;
+use crate::terrain::Terrain;
+use crate::versus::{Rival, RivalInput};
+use crate::waves::{formation_positions, Edge, SpawnArea, WaveDef, WaveLocation, Waves};
+use crate::zones::Zones;
+use glam::{vec2, Vec2};
+use static_aabb2d_index::{StaticAABB2DIndex, StaticAABB2DIndexBuilder};
+use std::f32::consts::TAU;
+
+const GRID_SIZE: f32 = 20.0;
+// distance from the sides of the map of the waves coming from them
+const EDGE_MARGIN: f32 = 30.0;
+
+/// Snapshot of the player input for a single simulation step
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Input {
+    pub guide_pos: Vec2,
+    pub is_guiding: bool,
+    pub camera_move: Vec2,
+    pub unlock: Option<Blessing>,
+    pub respec: Option<Blessing>,
+    /// Cast at the guide position
+    pub ability: Option<Ability>,
+    /// Second player, only used in versus mode
+    pub rival: RivalInput,
+}
+
+/// In versus mode `Win` means that the light side won and `Defeat` that the shadow did
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Win,
+    Defeat,
+}
+
+/// Game logic without any dependency on the window, input or time of the app
+pub struct Simulation {
+    pub config: GameConfig,
+    pub map: MapDef,
+    pub terrain: Terrain,
+    pub zones: Zones,
+    pub position: Vec2,
+    pub prev_position: Vec2,
+    pub souls: Vec<Soul>,
+    pub ids: u64,
+    pub rng: Rng,
+
+    pub blessings: Blessings,
+    pub params: Params,
+    pub abilities: Abilities,
+
+    // guide
+    pub guide_pos: Vec2,
+    pub is_guiding: bool,
+
+    // shadow agent
+    pub tempter: Option<Tempter>,
+    // second player in versus mode, replaces the tempter
+    pub rival: Option<Rival>,
+
+    // campaign level being played
+    pub level: Option<LevelDef>,
+    // challenge of the day, its modifiers change the rules
+    pub daily: Option<Daily>,
+
+    // rules of the mode, checked after each tick
+    pub win: Option<WinCondition>,
+    pub lose: LoseCondition,
+
+    // spawner
+    pub waves: Waves,
+
+    // stats
+    pub energy: u64,
+    pub good_progress: f32,
+    pub bad_progress: f32,
+    pub play_time: f32,
+    pub total_energy: u64,
+    /// Souls turned Luminal during the game
+    pub conversions: u64,
+
+    // world positions where energy was collected, drained by the renderer
+    pub energy_events: Vec<Vec2>,
+    // world positions where the souls of a wave appeared, drained by the renderer
+    pub arrival_events: Vec<Vec2>,
+    pub outcome: Option<Outcome>,
+    /// Why the game ended, from the condition that ended it
+    pub end_reason: Option<String>,
+}
+
+impl Simulation {
+    pub fn new(seed: u64, config: GameConfig, map: MapDef) -> Self {
+        let blessings = Blessings::new();
+        let params = blessings.params(&config.start, &config.end);
+        let map_size = map.size();
+        let position = map_size * 0.5;
+        let terrain = Terrain::new(map_size, &map.obstacles);
+        let zones = Zones::new(config.zones, &map.zones);
+        let waves = Waves::new(config.waves, &map.waves, config.initial_spawn_time);
+        // away from the player's souls at the beginning
+        let tempter = config.tempter.map(|c| Tempter::new(c, map_size * 0.75));
+        let win_threshold = config.win_threshold;
+
+        Self {
+            config,
+            map,
+            terrain,
+            zones,
+            position,
+            prev_position: position,
+            souls: vec![],
+            ids: 0,
+            rng: Rng::new(seed),
+
+            blessings,
+            params,
+            abilities: Abilities::default(),
+
+            guide_pos: Vec2::ZERO,
+            is_guiding: false,
+
+            tempter,
+            rival: None,
+
+            level: None,
+            daily: None,
+
+            win: Some(Box::new(Influence(win_threshold))),
+            lose: Box::new(NoInfluence),
+
+            waves,
+
+            energy: 0,
+            good_progress: 0.0,
+            bad_progress: 0.0,
+            play_time: 0.0,
+            total_energy: 0,
+            conversions: 0,
+
+            energy_events: vec![],
+            arrival_events: vec![],
+            outcome: None,
+            end_reason: None,
+        }
+    }
+
+    /// Turns the game into a match between two players, light vs shadow
+    pub fn start_versus(&mut self) {
+        let pos = self.terrain.map_size * 0.75;
+        self.rival = Some(Rival::new(pos, &self.config.start, &self.config.end));
+        self.tempter = None;
+
+        let threshold = self.config.versus_threshold;
+        self.win = Some(Box::new(Influence(threshold)));
+        self.lose = Box::new(Any::new(vec![
+            Box::new(ShadowInfluence(threshold)),
+            Box::new(NoInfluence),
+        ]));
+    }
+
+    pub fn is_versus(&self) -> bool {
+        self.rival.is_some()
+    }
+
+    /// Survival without a win threshold, the waves keep growing and the shadows with them
+    pub fn start_endless(&mut self) {
+        self.waves.endless = Some(self.config.endless);
+        // there's no way to win, only to last as long as possible
+        self.win = None;
+    }
+
+    pub fn is_endless(&self) -> bool {
+        self.waves.endless.is_some()
+    }
+
+    /// Plays a campaign level, it must be called before spawning the souls
+    pub fn start_level(&mut self, def: LevelDef) {
+        if let Some(souls) = &def.souls {
+            self.map.groups = souls.clone();
+        }
+
+        // the level is always lost without influence, on top of its own conditions
+        self.win = Some(def.win.build());
+        self.lose = match &def.lose {
+            Some(lose) => Box::new(Any::new(vec![Box::new(NoInfluence), lose.build()])),
+            None => Box::new(NoInfluence),
+        };
+        self.level = Some(def);
+    }
+
+    /// Challenge of the day, it must be called before spawning the souls
+    pub fn start_daily(&mut self, daily: Daily) {
+        if daily.has(Modifier::RestlessSouls) {
+            self.config.wanderers = (self.config.wanderers + 0.6).min(1.0);
+        }
+        if daily.has(Modifier::BigWaves) {
+            self.waves.config.max_count *= 2;
+        }
+
+        self.waves.scripted.extend(daily.waves.iter().cloned());
+        self.waves
+            .scripted
+            .sort_by(|a, b| a.time.total_cmp(&b.time));
+        self.daily = Some(daily);
+        self.refresh_params();
+    }
+
+    /// Campaign levels can restrict the blessings available
+    pub fn can_unlock(&self, b: Blessing) -> bool {
+        let allowed = match &self.level {
+            Some(l) => l.allows(b),
+            None => true,
+        };
+        allowed && self.blessings.can_unlock(b).is_ok()
+    }
+
+    pub fn score(&self) -> Score {
+        Score::new(
+            &self.config.endless,
+            self.play_time,
+            self.conversions,
+            self.total_energy,
+        )
+    }
+
+    /// Spawn point picked using their weights
+    fn random_spawn_point(&mut self) -> Option<usize> {
+        let total = self.map.spawn_points.iter().map(|p| p.weight).sum::<f32>();
+        if total <= 0.0 {
+            return None;
+        }
+
+        let mut value = self.rng.range(0.0..total);
+        self.map.spawn_points.iter().position(|p| {
+            value -= p.weight;
+            value < 0.0
+        })
+    }
+
+    fn wave_area(&mut self, location: &WaveLocation) -> SpawnArea {
+        let map_size = self.terrain.map_size;
+        let area = |center: Vec2, radius: f32, axis: Vec2| SpawnArea {
+            center,
+            radius,
+            axis,
+            bias: vec![],
+        };
+
+        let point = match location {
+            WaveLocation::Center => None,
+            WaveLocation::Random => self.random_spawn_point(),
+            WaveLocation::Point(name) => self.map.spawn_points.iter().position(|p| p.name == *name),
+            WaveLocation::Edge(edge) => {
+                let (w, h) = (map_size.x, map_size.y);
+                return match edge {
+                    Edge::North => area(vec2(w * 0.5, EDGE_MARGIN), w * 0.4, Vec2::X),
+                    Edge::South => area(vec2(w * 0.5, h - EDGE_MARGIN), w * 0.4, Vec2::X),
+                    Edge::West => area(vec2(EDGE_MARGIN, h * 0.5), h * 0.4, Vec2::Y),
+                    Edge::East => area(vec2(w - EDGE_MARGIN, h * 0.5), h * 0.4, Vec2::Y),
+                };
+            }
+        };
+
+        match point.map(|i| &self.map.spawn_points[i]) {
+            Some(p) => SpawnArea {
+                bias: p.kinds.clone(),
+                ..area(p.pos(), p.radius, Vec2::X)
+            },
+            None => area(map_size * 0.5, map_size.min_element() * 0.4, Vec2::X),
+        }
+    }
+
+    fn spawn_wave(&mut self, wave: &WaveDef) {
+        let area = self.wave_area(&wave.location);
+        let wanderers = wave.wanderers.unwrap_or(self.config.wanderers);
+        let positions = formation_positions(wave.formation, wave.count, &area, &mut self.rng);
+        for pos in positions {
+            let kind = wave.random_kind(&area.bias, &mut self.rng);
+            self.spawn_soul(pos, Some(kind), wanderers);
+            self.arrival_events.extend(self.souls.last().map(|s| s.pos));
+        }
+    }
+
+    pub fn init_spawn(&mut self) {
+        for i in 0..self.map.groups.len() {
+            let group = self.map.groups[i];
+            let (pos, radius) = (group.pos(), group.radius);
+            self.spawn_souls(group.count, group.kind, group.wanderers, pos, radius);
+        }
+    }
+
+    /// Spawns the souls in a circle, `wanderers` is the chance of each soul to travel across the map
+    pub fn spawn_souls(
+        &mut self,
+        n: usize,
+        kind: Option<SoulKind>,
+        wanderers: f32,
+        center: Vec2,
+        radius: f32,
+    ) {
+        for _ in 0..n {
+            let pos = center + radial_random_pos(&mut self.rng, radius);
+            self.spawn_soul(pos, kind, wanderers);
+        }
+    }
+
+    /// Random karma if `kind` is not set
+    pub fn spawn_soul(&mut self, pos: Vec2, kind: Option<SoulKind>, wanderers: f32) {
+        let range = match kind {
+            Some(k) => match k {
+                SoulKind::Luminal => 1.0..2.0,
+                SoulKind::Neutral => -0.9..0.9,
+                SoulKind::Shadow => -2.0..1.0,
+                SoulKind::Eternal => 5.0..6.0,
+            },
+            None => -2.0..2.0,
+        };
+
+        let karma = self.rng.range(range);
+        let pos = self
+            .terrain
+            .resolve(pos.round())
+            .clamp(Vec2::ZERO, self.terrain.map_size);
+        let movement = if self.rng.range(0.0..1.0) < wanderers {
+            Movement::Wander {
+                target: self.terrain.random_destination(pos, &mut self.rng),
+            }
+        } else {
+            Movement::Idle
+        };
+        self.souls.push(Soul {
+            id: self.ids,
+            karma,
+            pos,
+            prev_pos: pos,
+            vel: Vec2::ZERO,
+            is_following: false,
+            is_tempted: false,
+            energy_timer: self.params.energy_time,
+            movement,
+            visuals: VisualData::new(&mut self.rng),
+            conversion: KarmaConversion::Neutral,
+        });
+        self.ids += 1;
+    }
+
+    /// Advances the simulation one tick, `dt` is expected to be fixed
+    pub fn update(&mut self, dt: f32, input: &Input) {
+        self.prev_position = self.position;
+        self.souls.iter_mut().for_each(|s| s.prev_pos = s.pos);
+
+        self.position += self.config.camera_speed * input.camera_move * dt;
+
+        if self.outcome.is_some() {
+            return;
+        }
+
+        if let Some(b) = input.unlock {
+            self.unlock_blessing(b);
+        }
+
+        if let Some(b) = input.respec {
+            self.respec_blessing(b);
+        }
+
+        self.play_time += dt;
+        if self
+            .daily
+            .as_ref()
+            .is_some_and(|d| d.has(Modifier::ShrinkingCircle))
+        {
+            self.refresh_params();
+        }
+
+        self.guide_pos = input.guide_pos;
+        self.is_guiding = input.is_guiding;
+
+        self.abilities.update(dt);
+        if let Some(a) = input.ability {
+            self.cast_ability(a);
+        }
+
+        let map_size = self.terrain.map_size;
+        if let Some(rival) = &mut self.rival {
+            if let Some(b) = input.rival.unlock {
+                rival.unlock_blessing(b, &self.config.start, &self.config.end);
+            }
+            rival.update(dt, &input.rival, map_size);
+        }
+
+        // Manage the spawner
+        let waves = self.waves.update(
+            dt,
+            self.play_time,
+            self.params.slow_spawn_time,
+            self.params.block_spawn_souls,
+        );
+        waves.iter().for_each(|w| self.spawn_wave(w));
+
+        let mut good = 0;
+        let mut bad = 0;
+
+        let mut aabb_builder = StaticAABB2DIndexBuilder::<f32>::new(self.souls.len());
+
+        // update entities positions
+        let elapsed = self.play_time;
+        let recall_target = self.abilities.recall_target();
+        let wander_speed = self.config.wander_speed;
+
+        if let Some(tempter) = &mut self.tempter {
+            let player = self
+                .is_guiding
+                .then_some((self.guide_pos, self.params.sacred_radius));
+            tempter.update(dt, &self.souls, player, &self.terrain, &mut self.rng);
+        }
+
+        // the shadows follow the tempter or the second player
+        let shadow_guide = match (&self.tempter, &self.rival) {
+            (Some(t), _) => Some((t.pos, t.config.radius, t.config.following_speed)),
+            (_, Some(r)) if r.is_guiding => {
+                Some((r.pos, r.params.sacred_radius, r.params.following_speed))
+            }
+            _ => None,
+        };
+        let mut rival = self.rival.as_mut();
+        self.souls.iter_mut().for_each(|s| {
+            if s.is_good() {
+                good += 1;
+            } else if s.is_bad() {
+                bad += 1;
+            }
+
+            s.is_following = false;
+            s.is_tempted = false;
+
+            let is_good_soul = s.is_good();
+            // followers are moved later by the flock, once all the positions are known
+            if self.is_guiding
+                && is_good_soul
+                && is_close(s.pos, self.guide_pos, self.params.sacred_radius)
+            {
+                s.is_following = true;
+            }
+
+            if let (Some(target), true) = (recall_target, is_good_soul) {
+                let speed = self.params.following_speed * Ability::Recall.def().power * dt;
+                s.is_following = true;
+                if !is_close(s.pos, target, speed) {
+                    s.pos = move_towards(s.pos, target, speed);
+                }
+            }
+
+            if let Some((pos, radius, speed)) = shadow_guide {
+                if s.is_bad() && is_close(s.pos, pos, radius) {
+                    s.is_tempted = true;
+                    s.pos = move_towards(s.pos, pos, speed * dt);
+                }
+            }
+
+            s.update_movement(elapsed, dt, &self.terrain, wander_speed, &mut self.rng);
+            s.pos = s.pos.clamp(Vec2::ZERO, map_size); // keep my beloved souls inside the map
+
+            // collect energy
+            if is_good_soul {
+                s.energy_timer -= dt;
+                if s.energy_timer <= 0.0 {
+                    s.energy_timer = self.params.energy_time;
+                    self.energy += self.params.energy_amount;
+                    self.total_energy += self.params.energy_amount;
+                    self.energy_events.push(s.pos);
+                }
+            } else if let (Some(r), true) = (rival.as_mut(), s.is_bad()) {
+                s.energy_timer -= dt;
+                if s.energy_timer <= 0.0 {
+                    s.energy_timer = r.params.energy_time;
+                    r.energy += r.params.energy_amount;
+                    r.total_energy += r.params.energy_amount;
+                }
+            }
+
+            aabb_builder.add(s.pos.x, s.pos.y, s.pos.x + 16.0, s.pos.y + 16.0);
+        });
+
+        let aabb_index = aabb_builder.build().unwrap();
+
+        // recalled souls rush to the target without caring about the flock
+        if recall_target.is_none() {
+            if self.is_guiding {
+                self.terrain.update_flow(self.guide_pos);
+            }
+
+            flock(
+                &mut self.souls,
+                self.guide_pos,
+                &self.params,
+                &self.terrain,
+                dt,
+                &aabb_index,
+            );
+        }
+
+        avoid_overlap(
+            &mut self.souls,
+            GRID_SIZE,
+            recall_target.is_none(),
+            &aabb_index,
+        );
+
+        // obstacles have the last word, nothing can be inside them
+        self.souls.iter_mut().for_each(|s| {
+            s.pos = self.terrain.resolve(s.pos).clamp(Vec2::ZERO, map_size);
+        });
+
+        // update progress
+        self.good_progress = good as f32 / self.souls.len() as f32;
+        self.bad_progress = bad as f32 / self.souls.len() as f32;
+
+        // update entities karma
+        self.zones.update(dt, &self.souls);
+        let rules = KarmaRules {
+            radius: self.config.karma_change_radius,
+            rate: self.params.karma_change_rate,
+            bad_rate: self.shadow_change_rate(),
+            expire_rate: self.params.karma_expire_rate,
+            use_eternals: self.params.eternals,
+            vulnerable_eternals: self.level.as_ref().is_some_and(|l| l.vulnerable_eternals),
+            abilities: &self.abilities,
+            zones: &self.zones,
+        };
+        self.conversions += update_karma(&mut self.souls, dt, &rules, &aabb_index);
+
+        let snapshot = self.snapshot();
+        let won = self.win.as_mut().is_some_and(|w| w.update(dt, &snapshot));
+        let lost = self.lose.update(dt, &snapshot);
+        if won {
+            self.outcome = Some(Outcome::Win);
+            self.end_reason = self.win.as_ref().map(|w| w.reason());
+        } else if lost {
+            self.outcome = Some(Outcome::Defeat);
+            self.end_reason = Some(self.lose.reason());
+        }
+    }
+
+    /// State of the game checked by the win and lose conditions
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot {
+            good_progress: self.good_progress,
+            bad_progress: self.bad_progress,
+            play_time: self.play_time,
+            total_energy: self.total_energy,
+            conversions: self.conversions,
+            waves: self.waves.number,
+            corrupted_shrines: self
+                .zones
+                .list
+                .iter()
+                .filter(|z| z.kind == ZoneKind::Shrine && z.is_corrupted)
+                .count(),
+            ..Default::default()
+        };
+
+        self.souls.iter().for_each(|s| match s.kind() {
+            SoulKind::Neutral => snapshot.neutrals += 1,
+            SoulKind::Luminal => snapshot.luminals += 1,
+            SoulKind::Shadow => snapshot.shadows += 1,
+            SoulKind::Eternal => snapshot.eternals += 1,
+        });
+
+        snapshot
+    }
+
+    /// Recalculates the params after a change in the blessings
+    pub fn refresh_params(&mut self) {
+        self.params = self.blessings.params(&self.config.start, &self.config.end);
+        if let Some(daily) = &self.daily {
+            daily.apply(&mut self.params, self.play_time);
+        }
+    }
+
+    /// Price of the next level of the blessing
+    pub fn blessing_price(&self, b: Blessing) -> u64 {
+        b.price(self.blessings.level(&b)) * self.price_scale()
+    }
+
+    fn price_scale(&self) -> u64 {
+        self.daily.as_ref().map_or(1, |d| d.price_scale())
+    }
+
+    pub fn unlock_blessing(&mut self, b: Blessing) -> bool {
+        let price = self.blessing_price(b);
+        let can_unlock = self.can_unlock(b) && self.energy >= price;
+        if can_unlock {
+            let v = self.blessings.unlock(b);
+            if v {
+                self.energy -= price;
+                self.refresh_params();
+            }
+
+            return v;
+        }
+
+        false
+    }
+
+    /// Removes a level of the blessing giving back part of the energy spent on it
+    pub fn respec_blessing(&mut self, b: Blessing) -> bool {
+        let Some(spent) = self.blessings.remove_level(b) else {
+            return false;
+        };
+
+        let spent = spent * self.price_scale();
+        self.energy += (spent as f32 * self.config.refund_rate) as u64;
+        self.refresh_params();
+        true
+    }
+
+    /// Rate at which the shadows corrupt nearby souls, the rival's blessings improve it
+    pub fn shadow_change_rate(&self) -> f32 {
+        let params = self.rival.as_ref().map_or(&self.params, |r| &r.params);
+        let scale = self.daily.as_ref().map_or(1.0, |d| d.shadow_scale());
+        params.karma_change_rate * 0.2 * scale
+    }
+
+    pub fn can_cast(&self, a: Ability) -> bool {
+        self.blessings.has_ability(a) && self.abilities.is_ready(a) && self.energy >= a.def().cost
+    }
+
+    pub fn cast_ability(&mut self, a: Ability) -> bool {
+        if !self.can_cast(a) {
+            return false;
+        }
+
+        self.energy -= a.def().cost;
+        self.abilities.cast(a, self.guide_pos);
+        true
+    }
+}
+
+pub(crate) fn radial_random_pos(rng: &mut Rng, radius: f32) -> Vec2 {
+    let angle = rng.range(0.0..TAU);
+    let r = rng.range(0.0..1.0).sqrt() * radius;
+    vec2(r * angle.cos(), r * angle.sin())
+}
+
+pub fn move_towards(from: Vec2, to: Vec2, speed: f32) -> Vec2 {
+    let direction = (to - from).normalize_or_zero();
+    let movement = direction * speed;
+    from + movement
+}
+
+pub fn is_close(entity_pos: Vec2, p2: Vec2, radius: f32) -> bool {
+    let dist = entity_pos.distance_squared(p2);
+    let r = radius * radius;
+    dist <= r
+}
+
+/// Steers the following souls as a flock, separation, alignment and cohesion
+/// keep them together without piling up while they seek the guide around the obstacles
+fn flock(
+    souls: &mut [Soul],
+    target: Vec2,
+    params: &Params,
+    terrain: &Terrain,
+    dt: f32,
+    aabb_index: &StaticAABB2DIndex<f32>,
+) {
+    // how fast the souls change their velocity to the desired one
+    const STEER_RATE: f32 = 8.0;
+
+    let speed = params.following_speed;
+    let radius = params.flock_radius;
+    // followers slow down when they're close to the guide, so they don't overshoot it
+    let slow_radius = params.sacred_radius * 0.5;
+
+    let velocities = souls
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.is_following)
+        .map(|(i, soul)| {
+            let pos = soul.pos;
+            let min = pos - radius;
+            let max = pos + radius;
+
+            let mut separation = Vec2::ZERO;
+            let mut alignment = Vec2::ZERO;
+            let mut center = Vec2::ZERO;
+            let mut neighbours = 0;
+            for n in aabb_index.query(min.x, min.y, max.x, max.y) {
+                let other = &souls[n];
+                if n == i || !other.is_following {
+                    continue;
+                }
+
+                let distance = pos.distance(other.pos);
+                if distance >= radius {
+                    continue;
+                }
+
+                separation += (pos - other.pos).normalize_or_zero() * (1.0 - distance / radius);
+                alignment += other.vel;
+                center += other.pos;
+                neighbours += 1;
+            }
+
+            let distance = pos.distance(target);
+            let arrive = (distance / slow_radius).min(1.0);
+            let seek = terrain.direction(pos, target) * speed * arrive;
+
+            let mut desired =
+                seek * params.flock_seek + separation * speed * params.flock_separation;
+            if neighbours > 0 {
+                let n = neighbours as f32;
+                let cohesion = (center / n - pos).normalize_or_zero() * speed;
+                desired +=
+                    (alignment / n) * params.flock_alignment + cohesion * params.flock_cohesion;
+            }
+
+            let vel = soul.vel + (desired - soul.vel) * (STEER_RATE * dt).min(1.0);
+            (i, vel.clamp_length_max(speed))
+        })
+        .collect::<Vec<_>>();
+
+    souls
+        .iter_mut()
+        .filter(|s| !s.is_following)
+        .for_each(|s| s.vel = Vec2::ZERO);
+
+    for (i, vel) in velocities {
+        souls[i].vel = vel;
+        souls[i].pos += vel * dt;
+    }
+}
+
+/// `is_flocking` is false when the followers skipped the flock, so they need to be kept apart here
+fn avoid_overlap(
+    souls: &mut [Soul],
+    min_distance: f32,
+    is_flocking: bool,
+    aabb_index: &StaticAABB2DIndex<f32>,
+) {
+    const REGULAR_FORCE_MULT: f32 = 0.5;
+    const FOLLOWING_FORCE_MUL: f32 = 1.0;
+
+    for i in 0..souls.len() {
+        let p1 = souls[i].pos;
+        let min = p1 - min_distance;
+        // TODO this size is hardcoded and don't check anchors...
+        let max = p1 + 16.0 + min_distance;
+        let close_souls = aabb_index.query(min.x, min.y, max.x, max.y);
+        for n in close_souls {
+            if i == n {
+                continue;
+            }
+
+            // the flock already keeps the followers apart
+            if is_flocking && souls[i].is_following && souls[n].is_following {
+                continue;
+            }
+
+            let p2 = souls[n].pos;
+            let distance = p1.distance(p2);
+            if distance < min_distance {
+                let overlap = min_distance - distance;
+                let direction = (p1 - p2).normalize_or_zero();
+                let is_following = souls[i].is_following
+                    || souls[n].is_following
+                    || souls[i].is_tempted
+                    || souls[n].is_tempted;
+                let force_mul = if is_following {
+                    FOLLOWING_FORCE_MUL
+                } else {
+                    REGULAR_FORCE_MULT
+                };
+
+                souls[i].pos += direction * (overlap * force_mul);
+                souls[n].pos -= direction * (overlap * force_mul);
+            }
+        }
+    }
+}
+
+/// Everything that changes the karma of the souls during a tick
+pub struct KarmaRules<'a> {
+    pub radius: f32,
+    pub rate: f32,
+    /// Rate at which the shadows corrupt the souls around them
+    pub bad_rate: f32,
+    pub expire_rate: f32,
+    pub use_eternals: bool,
+    /// The shadows can corrupt the Eternals too
+    pub vulnerable_eternals: bool,
+    pub abilities: &'a Abilities,
+    pub zones: &'a Zones,
+}
+
+pub fn update_karma(
+    souls: &mut [Soul],
+    dt: f32,
+    rules: &KarmaRules,
+    aabb_index: &StaticAABB2DIndex<f32>,
+) -> u64 {
+    let KarmaRules {
+        radius,
+        rate,
+        bad_rate,
+        expire_rate,
+        use_eternals,
+        vulnerable_eternals,
+        abilities,
+        zones,
+    } = *rules;
+    let radius_squared = radius * radius;
+
+    // Store karma changes to apply later
+    let karma_updates = souls
+        .iter()
+        .enumerate()
+        .map(|(i, soul)| {
+            let mut conversion = KarmaConversion::Neutral;
+
+            // Eternals are immune to corruption, unless the level says otherwise
+            let is_eternal = matches!(soul.kind(), SoulKind::Eternal);
+            if is_eternal && !vulnerable_eternals {
+                return (i, soul.karma, conversion);
+            }
+
+            let max_karma = if use_eternals { 6.0 } else { 2.0 };
+
+            let pos = soul.pos;
+            let min = pos - radius;
+            let max = pos + radius;
+            let close_souls = aabb_index.query(min.x, min.y, max.x, max.y);
+
+            let mut good_souls = 0;
+            let mut bad_souls = 0;
+
+            // Count good and bad souls in the radius
+            for n in close_souls {
+                if n == i {
+                    continue;
+                }
+
+                let other_soul = &souls[n];
+                if other_soul.pos.distance_squared(pos) <= radius_squared {
+                    match other_soul.kind() {
+                        SoulKind::Shadow => bad_souls += 1,
+                        // wanderers spread their karma wherever they go
+                        SoulKind::Luminal
+                            if other_soul.is_following || other_soul.is_wanderer() =>
+                        {
+                            good_souls += 1
+                        }
+                        SoulKind::Eternal => good_souls += 1,
+                        _ => {}
+                    }
+                }
+            }
+
+            // only the shadows can change the karma of a vulnerable Eternal
+            if is_eternal {
+                let (_, frozen) = abilities.karma_modifiers(pos);
+                let (_, blocked) = zones.karma_modifiers(pos);
+                if bad_souls > good_souls && !frozen && !blocked {
+                    return (i, soul.karma - bad_rate * dt, KarmaConversion::Bad);
+                }
+
+                return (i, soul.karma, conversion);
+            }
+
+            let expiration = expire_rate * dt;
+            let mut karma = if soul.is_following {
+                soul.karma + expiration
+            } else {
+                soul.karma - expiration
+            };
+
+            if bad_souls > good_souls {
+                karma = (karma - bad_rate * dt).max(-2.0);
+                conversion = KarmaConversion::Bad;
+            } else if good_souls > bad_souls {
+                let extra = (good_souls as f32 / 50.0).clamp(0.0, 1.0);
+                let new_rate = rate + extra;
+                karma = (karma + new_rate * dt).min(max_karma);
+                conversion = KarmaConversion::Good;
+            }
+
+            let (boost, frozen) = abilities.karma_modifiers(pos);
+            if frozen {
+                karma = karma.max(soul.karma);
+                if matches!(conversion, KarmaConversion::Bad) {
+                    conversion = KarmaConversion::Neutral;
+                }
+            }
+
+            if boost > 0.0 {
+                karma += boost * dt;
+                conversion = KarmaConversion::Good;
+            }
+
+            let (zone_rate, blocked) = zones.karma_modifiers(pos);
+            if blocked {
+                // nobody can change anyone's mind inside a sanctuary
+                return (i, soul.karma, KarmaConversion::Neutral);
+            }
+
+            if zone_rate > 0.0 {
+                karma += zone_rate * dt;
+                conversion = KarmaConversion::Good;
+            } else if zone_rate < 0.0 {
+                karma += zone_rate * dt;
+                conversion = KarmaConversion::Bad;
+            }
+
+            (i, karma.clamp(-2.0, max_karma), conversion)
+        })
+        .collect::<Vec<_>>();
+
+    // update the karma
+    let mut converted = 0;
+    for (i, new_karma, conversion) in karma_updates {
+        if !souls[i].is_good() && new_karma >= 1.0 {
+            converted += 1;
+        }
+
+        souls[i].karma = new_karma;
+        souls[i].conversion = conversion;
+    }
+
+    converted
+}
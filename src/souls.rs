@@ -1,8 +1,10 @@
-use rkit::math::{vec2, Vec2};
-use rkit::random;
+use crate::rng::Rng;
+use crate::terrain::Terrain;
+use glam::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
 use std::f32::consts::TAU;
 
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
 pub enum SoulKind {
     #[default]
     Neutral,
@@ -18,10 +20,10 @@ pub struct VisualData {
 }
 
 impl VisualData {
-    pub fn new() -> Self {
+    pub fn new(rng: &mut Rng) -> Self {
         let pos_offset = Vec2::ZERO;
-        let phase_shift = random::range(0.0..TAU);
-        let speed_multiplier = random::range(0.8..1.2);
+        let phase_shift = rng.range(0.0..TAU);
+        let speed_multiplier = rng.range(0.8..1.2);
         Self {
             pos_offset,
             phase_shift,
@@ -41,7 +43,17 @@ impl VisualData {
     }
 }
 
-#[derive(Copy, Clone)]
+/// How a soul moves around when it's not following anyone
+#[derive(Copy, Clone, Debug, Default)]
+pub enum Movement {
+    /// Stays around its position
+    #[default]
+    Idle,
+    /// Travels across the map, picking a new destination each time it arrives
+    Wander { target: Vec2 },
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum KarmaConversion {
     Neutral,
     Good,
@@ -52,8 +64,13 @@ pub struct Soul {
     pub id: u64,
     pub karma: f32,
     pub pos: Vec2,
+    pub prev_pos: Vec2,
+    /// Steering velocity, only used while following the guide
+    pub vel: Vec2,
     pub is_following: bool,
+    pub is_tempted: bool,
     pub energy_timer: f32,
+    pub movement: Movement,
     pub visuals: VisualData,
     pub conversion: KarmaConversion,
 }
@@ -89,12 +106,42 @@ impl Soul {
         self.karma > -1.0 && self.karma < 1.0
     }
 
-    pub fn idle_movement(&mut self, t: f32, dt: f32) {
+    /// Position to render between the last two simulation ticks
+    pub fn interpolated_pos(&self, alpha: f32) -> Vec2 {
+        self.prev_pos.lerp(self.pos, alpha)
+    }
+
+    pub fn is_wanderer(&self) -> bool {
+        matches!(self.movement, Movement::Wander { .. })
+    }
+
+    pub fn update_movement(
+        &mut self,
+        t: f32,
+        dt: f32,
+        terrain: &Terrain,
+        speed: f32,
+        rng: &mut Rng,
+    ) {
         // move the souls a bit of their position
-        let h_mul = random::range(-0.1..0.1);
-        let v_mul = random::range(-0.1..0.1);
+        let h_mul = rng.range(-0.1..0.1);
+        let v_mul = rng.range(-0.1..0.1);
         self.pos += vec2(h_mul, v_mul) * dt * 200.0;
 
+        // wanderers stop traveling while they're being guided
+        let is_guided = self.is_following || self.is_tempted;
+        if let (Movement::Wander { target }, false) = (&mut self.movement, is_guided) {
+            let direction = (*target - self.pos).normalize_or_zero();
+            let step = speed * dt;
+            // being pushed around can leave an obstacle in the way, then it goes somewhere else
+            let arrived = self.pos.distance_squared(*target) <= step * step;
+            if arrived || !terrain.is_clear(self.pos, *target) {
+                *target = terrain.random_destination(self.pos, rng);
+            } else {
+                self.pos += direction * step;
+            }
+        }
+
         // increase visual movement
         self.visuals.idle_movement(t);
     }
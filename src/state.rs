@@ -1,286 +1,627 @@
-use crate::params::{Blessing, Blessings, Params, PARAMS_END, PARAMS_START};
 use crate::resources::Resources;
-use crate::souls::{KarmaConversion, Soul, SoulKind, VisualData};
-use rkit::app::{window_height, window_size};
+use ld56::abilities::Ability;
+use ld56::campaign::{load_level, load_levels, CampaignProgress, LevelDef};
+use ld56::config::{load_presets, GameConfig, DEFAULT_PRESET};
+use ld56::daily::{Daily, DailyRecords, DailyResult};
+use ld56::map::{load_map, load_maps, MapDef, DEFAULT_MAP};
+use ld56::mapgen::{generate, GENERATED_PREFIX};
+use ld56::params::{load_blessings, Blessing};
+use ld56::replay::{Playback, Replay};
+#[cfg(not(target_arch = "wasm32"))]
+use ld56::save::SaveData;
+use ld56::sim::{Input, Outcome, Simulation};
+use ld56::versus::RivalInput;
+use rkit::app::window_size;
 use rkit::draw::{Camera2D, Draw2D, ScreenMode};
 use rkit::input::{
-    is_key_down, is_key_pressed, is_mouse_btn_down, mouse_position, mouse_wheel_delta, KeyCode,
-    MouseButton,
+    is_key_down, is_key_pressed, is_mouse_btn_down, mouse_position, KeyCode, MouseButton,
 };
-use rkit::math::{vec2, Vec2};
-use rkit::random;
+use rkit::math::Vec2;
 use rkit::time;
-use static_aabb2d_index::{StaticAABB2DIndex, StaticAABB2DIndexBuilder};
-use std::f32::consts::TAU;
-use strum::IntoEnumIterator;
 
 #[derive(Copy, Clone)]
 pub enum Mode {
     Menu,
+    LevelSelect,
     Playing,
     Win,
     Defeat,
+    LevelComplete,
 }
 
-pub const MAP_SIZE: Vec2 = Vec2::splat(1000.0);
+const DEFAULT_TICK_RATE: f32 = 60.0;
+// avoid the spiral of death if a frame takes too long (tab in background, etc...)
+const MAX_FRAME_TIME: f32 = 0.25;
+
 // pub const RESOLUTION: Vec2 = Vec2::new(960.0, 540.0);
 pub const RESOLUTION: Vec2 = Vec2::new(640.0, 360.0);
-const CAMERA_SPEED: f32 = 120.0;
-const GRID_SIZE: f32 = 20.0;
-const KARMA_CHANGE_RADIUS: f32 = 40.0;
-const INITIAL_SPAWN_TIME: f32 = 20.0;
 
 pub struct State {
     pub mode: Mode,
 
     pub camera: Camera2D,
-    pub position: Vec2,
-    pub souls: Vec<Soul>,
-    pub ids: u64,
+    pub sim: Simulation,
+
+    // difficulty
+    pub presets: Vec<GameConfig>,
+    pub difficulty: usize,
+
+    // world layout, the last map is a generated one
+    pub maps: Vec<MapDef>,
+    pub map: usize,
 
-    pub blessings: Blessings,
-    pub params: Params,
+    // campaign, `level` is the one selected or being played
+    pub levels: Vec<LevelDef>,
+    pub level: usize,
+    pub progress: CampaignProgress,
+
+    // best result of each daily challenge, and if the last run beat it
+    pub daily_records: DailyRecords,
+    pub is_daily_best: bool,
+
+    // fixed timestep
+    pub tick_rate: f32,
+    pub accumulator: f32,
+    pub alpha: f32, // interpolation between the last two ticks
 
     // mouse
     pub mouse_pos: Vec2,
-    pub is_guiding: bool,
+    pub pending_unlocks: Vec<Blessing>,
+    pub pending_respecs: Vec<Blessing>,
+    pub pending_abilities: Vec<Ability>,
+
+    // second player in versus mode, it picks blessings with the keyboard
+    pub rival_selected: usize,
+    pub pending_rival_unlocks: Vec<Blessing>,
 
-    // spawner
-    pub spawn_time: f32,  // time to reset timer
-    pub spawn_timer: f32, // current spawn timer
-    pub spawn_num: usize, // number of souls spawned
+    // replays
+    pub recording: Option<Replay>,
+    pub playback: Option<Playback>,
 
-    // stats
-    pub energy: u64,
-    pub good_progress: f32,
-    pub bad_progress: f32,
-    pub play_time: f32,
-    pub total_energy: u64,
+    // why the last action of the menu failed, shown until the next key is pressed
+    pub menu_error: Option<String>,
 
     // res
     pub res: Resources,
 
     // visual
     pub energy_positions: Vec<Vec2>,
+    // world positions of the last arrivals and the time since they came
+    pub arrivals: Vec<(Vec2, f32)>,
 }
 
 impl State {
     pub fn new() -> Result<Self, String> {
+        load_blessings()?;
         let camera = Camera2D::new(window_size(), ScreenMode::AspectFit(RESOLUTION));
-        let position = MAP_SIZE * 0.5;
-        let blessings = Blessings::new();
-        let params = blessings.params();
-        let res = Resources::new()?;
+        let presets = load_presets()?;
+        let difficulty = presets
+            .iter()
+            .position(|p| p.name == DEFAULT_PRESET)
+            .unwrap_or(0);
+        let mut maps = load_maps()?;
+        maps.push(generate(fastrand::u32(..) as _));
+        let map = maps.iter().position(|m| m.id == DEFAULT_MAP).unwrap_or(0);
+        let sim = Simulation::new(
+            fastrand::u64(..),
+            presets[difficulty].clone(),
+            maps[map].clone(),
+        );
+        let mut res = Resources::new()?;
+        res.set_background(maps[map].background);
+        let levels = load_levels()?;
+        let progress = CampaignProgress::load().unwrap_or_else(|e| {
+            eprintln!("Error loading the campaign progress: {e}");
+            CampaignProgress::default()
+        });
+        let daily_records = DailyRecords::load().unwrap_or_else(|e| {
+            eprintln!("Error loading the daily records: {e}");
+            DailyRecords::default()
+        });
 
         Ok(Self {
             mode: Mode::Menu,
 
             camera,
-            position,
-            souls: vec![],
-            ids: 0,
+            sim,
+
+            presets,
+            difficulty,
+
+            maps,
+            map,
+
+            levels,
+            level: 0,
+            progress,
 
-            blessings,
-            params,
+            daily_records,
+            is_daily_best: false,
+
+            tick_rate: DEFAULT_TICK_RATE,
+            accumulator: 0.0,
+            alpha: 1.0,
 
             mouse_pos: Vec2::ZERO,
-            is_guiding: false,
+            pending_unlocks: vec![],
+            pending_respecs: vec![],
+            pending_abilities: vec![],
+
+            rival_selected: 0,
+            pending_rival_unlocks: vec![],
 
-            spawn_time: INITIAL_SPAWN_TIME,
-            spawn_timer: INITIAL_SPAWN_TIME,
-            spawn_num: 1,
+            recording: None,
+            playback: None,
 
-            energy: 0,
-            good_progress: 0.0,
-            bad_progress: 0.0,
+            menu_error: None,
 
-            play_time: 0.0,
-            total_energy: 0,
             res,
 
             energy_positions: Vec::with_capacity(200),
+            arrivals: vec![],
         })
     }
 
-    pub fn spawn_souls(&mut self, n: usize, kind: Option<SoulKind>) {
-        let map_radius = MAP_SIZE.min_element() * 0.4;
-        for i in 0..n {
-            let range = match kind {
-                Some(k) => match k {
-                    SoulKind::Luminal => 1.0..2.0,
-                    SoulKind::Neutral => -0.9..0.9,
-                    SoulKind::Shadow => -2.0..1.0,
-                    SoulKind::Eternal => 5.0..6.0,
-                },
-                None => -2.0..2.0,
-            };
-
-            let karma: f32 = random::range(range);
-            let pos = (MAP_SIZE * 0.5 + radial_random_pos(map_radius)).round();
-            self.souls.push(Soul {
-                id: self.ids,
-                karma,
-                pos,
-                is_following: false,
-                energy_timer: self.params.energy_time,
-                visuals: VisualData::new(),
-                conversion: KarmaConversion::Neutral,
-            });
-            self.ids += 1;
-        }
-    }
-
     pub fn update(&mut self) {
-        let elapsed = time::elapsed_f32();
-        let dt = time::delta_f32();
+        let dt = time::delta_f32().min(MAX_FRAME_TIME);
 
-        self.update_camera(dt);
+        self.update_camera();
 
-        if !matches!(self.mode, Mode::Playing) {
-            return;
-        }
+        if matches!(self.mode, Mode::Playing) {
+            if let Some(a) = ability_pressed() {
+                self.queue_ability(a);
+            }
 
-        self.play_time += dt;
-        self.is_guiding = is_guiding_souls();
-
-        // Manage the spawner
-        self.spawn_timer -= dt;
-        if self.spawn_timer <= 0.0 {
-            self.spawn_time = (self.spawn_time - 0.5).max(5.0);
-            self.spawn_timer = self.spawn_time + self.params.slow_spawn_time;
-            self.spawn_num = (self.spawn_num + 1).min(20);
-            let souls_to_spawn = self
-                .spawn_num
-                .checked_sub(self.params.block_spawn_souls)
-                .unwrap_or(1);
-            self.spawn_souls(souls_to_spawn, Some(SoulKind::Neutral));
-        }
+            if self.sim.is_versus() {
+                self.update_rival_selection();
+            }
+
+            let fixed_dt = self.fixed_dt();
+            let live_input = self.read_input();
+
+            self.accumulator += dt;
+            while self.accumulator >= fixed_dt {
+                let input = match &mut self.playback {
+                    Some(playback) => match playback.next_input() {
+                        Some(input) => input,
+                        None => break,
+                    },
+                    None => Input {
+                        unlock: self.pending_unlocks.pop(),
+                        respec: self.pending_respecs.pop(),
+                        ability: self.pending_abilities.pop(),
+                        rival: RivalInput {
+                            unlock: self.pending_rival_unlocks.pop(),
+                            ..live_input.rival
+                        },
+                        ..live_input
+                    },
+                };
 
-        let mut good = 0;
-        let mut bad = 0;
+                if let Some(replay) = &mut self.recording {
+                    replay.record(&input);
+                }
 
-        let mut aabb_builder = StaticAABB2DIndexBuilder::<f32>::new(self.souls.len());
+                self.sim.update(fixed_dt, &input);
+                self.accumulator -= fixed_dt;
 
-        // update entities positions
-        self.souls.iter_mut().for_each(|s| {
-            if s.is_good() {
-                good += 1;
-            } else if s.is_bad() {
-                bad += 1;
+                if self.sim.outcome.is_some() {
+                    self.accumulator = 0.0;
+                    break;
+                }
             }
 
-            s.is_following = false;
+            self.alpha = (self.accumulator / fixed_dt).min(1.0);
 
-            let is_good_soul = s.is_good();
-            if self.is_guiding
-                && is_good_soul
-                && is_close(s.pos, self.mouse_pos, self.params.sacred_radius)
+            // the replay was stopped before the end of the game, there's nothing else to play
+            if self.sim.outcome.is_none() && self.playback.as_ref().is_some_and(|p| p.is_finished())
             {
-                s.is_following = true;
-                s.pos = move_towards(s.pos, self.mouse_pos, self.params.following_speed * dt);
+                self.playback = None;
+                self.menu_error = Some("The replay ended before the game was over".to_string());
+                self.open_menu();
+                return;
             }
 
-            s.idle_movement(elapsed, dt);
-            s.pos = s.pos.clamp(Vec2::ZERO, MAP_SIZE); // keep my beloved souls inside the map
-
-            // collect energy
-            if is_good_soul {
-                s.energy_timer -= dt;
-                if s.energy_timer <= 0.0 {
-                    s.energy_timer = self.params.energy_time;
-                    self.energy += self.params.energy_amount;
-                    self.total_energy += self.params.energy_amount;
-                    self.energy_positions
-                        .push(self.camera.local_to_screen(s.pos));
-                }
+            match self.sim.outcome {
+                Some(Outcome::Win) if self.sim.level.is_some() => self.complete_level(),
+                Some(Outcome::Win) => self.mode = Mode::Win,
+                Some(Outcome::Defeat) => self.mode = Mode::Defeat,
+                None => {}
             }
 
-            aabb_builder.add(s.pos.x, s.pos.y, s.pos.x + 16.0, s.pos.y + 16.0);
-        });
+            if self.sim.outcome.is_some() {
+                self.save_recording();
+                self.record_daily();
+            }
+        }
 
-        let aabb_index = aabb_builder.build().unwrap();
+        // energy is collected in world space but the ui displays it in screen space
+        let camera = &self.camera;
+        self.energy_positions.extend(
+            self.sim
+                .energy_events
+                .drain(..)
+                .map(|p| camera.local_to_screen(p)),
+        );
+        self.arrivals
+            .extend(self.sim.arrival_events.drain(..).map(|p| (p, 0.0)));
+    }
 
-        avoid_overlap(&mut self.souls, GRID_SIZE, &aabb_index);
+    /// Sets the simulation rate in ticks per second
+    pub fn set_tick_rate(&mut self, rate: f32) {
+        self.tick_rate = rate.max(1.0);
+    }
 
-        // update progress
-        self.good_progress = good as f32 / self.souls.len() as f32;
-        self.bad_progress = bad as f32 / self.souls.len() as f32;
+    pub fn fixed_dt(&self) -> f32 {
+        1.0 / self.tick_rate
+    }
 
-        // update entities karma
-        update_karma(
-            &mut self.souls,
-            dt,
-            KARMA_CHANGE_RADIUS,
-            self.params.karma_change_rate,
-            self.params.karma_expire_rate,
-            self.params.eternals,
-            &aabb_index,
-        );
+    pub fn set_difficulty(&mut self, idx: usize) {
+        self.difficulty = idx.min(self.presets.len() - 1);
+        self.reset_sim();
+    }
+
+    pub fn set_map(&mut self, idx: usize) {
+        self.map = idx.min(self.maps.len() - 1);
+        self.reset_sim();
+    }
+
+    pub fn is_generated_map(&self) -> bool {
+        self.maps[self.map].id.starts_with(GENERATED_PREFIX)
+    }
+
+    /// Replaces the generated map with a new one
+    pub fn generate_map(&mut self) {
+        if let Some(map) = self.maps.last_mut() {
+            *map = generate(fastrand::u32(..) as _);
+        }
+        self.set_map(self.maps.len() - 1);
+    }
+
+    /// Adds the exported map before the generated one, keeping the generated one selected
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn export_map(&mut self) -> Result<String, String> {
+        let map = self.maps[self.map].export()?;
+        let id = map.id.clone();
+        self.maps.insert(self.maps.len() - 1, map);
+        self.map = self.maps.len() - 1;
+        Ok(id)
+    }
+
+    fn reset_sim(&mut self) {
+        let config = self.presets[self.difficulty].clone();
+        let map = self.maps[self.map].clone();
+        self.res.set_background(map.background);
+        self.sim = Simulation::new(self.sim.rng.seed(), config, map);
+    }
 
-        if self.good_progress >= 0.98 {
-            self.mode = Mode::Win;
-        } else if self.good_progress <= 0.0 {
-            self.mode = Mode::Defeat;
+    fn map_def(&self, id: &str) -> Result<MapDef, String> {
+        // generated maps aren't in the list unless they're the current one
+        match self.maps.iter().find(|m| m.id == id) {
+            Some(map) => Ok(map.clone()),
+            None => load_map(id),
         }
     }
 
-    pub fn unlock_blessing(&mut self, b: Blessing) -> bool {
-        let lvl = self.blessings.level(&b);
-        let price = b.price(lvl);
-        let can_unlock = self.blessings.can_unlock(b) && self.energy >= price;
-        if can_unlock {
-            let v = self.blessings.unlock(b);
-            if v {
-                self.energy -= price;
-                self.params = self.blessings.params();
+    /// Also selects the level, so the next one can be played after it
+    fn select_level_def(&mut self, id: &str) -> Result<LevelDef, String> {
+        match self.levels.iter().position(|l| l.id == id) {
+            Some(idx) => {
+                self.level = idx;
+                Ok(self.levels[idx].clone())
             }
+            None => load_level(id),
+        }
+    }
+
+    fn preset(&self, name: &str) -> Result<GameConfig, String> {
+        self.presets
+            .iter()
+            .find(|p| p.name == name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown difficulty preset '{name}'"))
+    }
+
+    pub fn start_game(&mut self) {
+        self.mode = Mode::Playing;
+        self.clear_pending_inputs();
+        self.sim.init_spawn();
+        let mut replay = Replay::new(
+            self.sim.rng.seed(),
+            self.tick_rate,
+            &self.sim.config.name,
+            &self.sim.map.id,
+        );
+        replay.versus = self.sim.is_versus();
+        replay.endless = self.sim.is_endless();
+        replay.level = self.sim.level.as_ref().map(|l| l.id.clone());
+        replay.daily = self.sim.daily.as_ref().map(|d| d.day);
+        self.recording = Some(replay);
+    }
+
+    /// Clicks and keys waiting for the next tick belong to the previous run
+    fn clear_pending_inputs(&mut self) {
+        self.pending_unlocks.clear();
+        self.pending_respecs.clear();
+        self.pending_abilities.clear();
+        self.pending_rival_unlocks.clear();
+    }
+
+    /// Goes back to the menu with a regular game ready to start
+    pub fn open_menu(&mut self) {
+        self.reset_sim();
+        self.mode = Mode::Menu;
+    }
 
-            return v;
+    pub fn open_level_select(&mut self) {
+        self.mode = Mode::LevelSelect;
+    }
+
+    pub fn is_level_available(&self, idx: usize) -> bool {
+        idx < self.levels.len() && self.progress.is_available(&self.levels, idx)
+    }
+
+    /// Plays a campaign level with its own map and difficulty
+    pub fn start_level(&mut self, idx: usize) -> Result<(), String> {
+        if !self.is_level_available(idx) {
+            return Err("The previous level must be completed first".to_string());
         }
 
-        return false;
+        let def = self.levels[idx].clone();
+        let config = self.preset(&def.difficulty)?;
+        let map = self.map_def(&def.map)?;
+        self.res.set_background(map.background);
+        self.sim = Simulation::new(fastrand::u64(..), config, map);
+        self.sim.start_level(def);
+        self.level = idx;
+        self.energy_positions.clear();
+        self.arrivals.clear();
+        self.start_game();
+        Ok(())
     }
 
-    pub fn apply_camera(&self, draw: &mut Draw2D) {
-        draw.set_camera(&self.camera);
+    /// Challenge of the day, the same map, waves and modifiers for everybody
+    pub fn start_daily(&mut self, day: u64) -> Result<(), String> {
+        let daily = Daily::new(day);
+        let config = self.preset(DEFAULT_PRESET)?;
+        let map = generate(daily.seed());
+        self.res.set_background(map.background);
+        self.sim = Simulation::new(daily.seed(), config, map);
+        self.sim.start_daily(daily);
+        self.is_daily_best = false;
+        self.energy_positions.clear();
+        self.arrivals.clear();
+        self.start_game();
+        Ok(())
     }
 
-    fn update_camera(&mut self, dt: f32) {
-        self.camera_movement(dt);
-        self.camera.set_size(window_size().floor());
-        self.camera.set_position(self.position.floor());
+    fn record_daily(&mut self) {
+        let Some(daily) = &self.sim.daily else {
+            return;
+        };
 
-        // FIXME not working on the engine, delta is always a positive number using wheel (works with trackpad)
-        // let w_dt = mouse_wheel_delta().y;
-        // if !w_dt.is_zero() {
-        //     println!("{w_dt}");
-        //     let zoom = (self.camera.zoom() + w_dt);
-        //     self.camera.set_zoom(zoom);
-        // }
+        if self.is_playback() {
+            return;
+        }
 
-        self.camera.update();
-        self.mouse_pos = self.camera.screen_to_local(mouse_position());
+        let result = DailyResult {
+            won: self.sim.outcome == Some(Outcome::Win),
+            time: self.sim.play_time,
+            score: self.sim.score().total(),
+        };
+        self.is_daily_best = self.daily_records.record(daily.day, result);
+
+        if let Err(e) = self.daily_records.save() {
+            eprintln!("Error saving the daily records: {e}");
+        }
+    }
+
+    fn complete_level(&mut self) {
+        self.mode = Mode::LevelComplete;
+        if self.is_playback() {
+            return;
+        }
+
+        if let Some(level) = &self.sim.level {
+            self.progress.complete(&level.id);
+        }
+
+        if let Err(e) = self.progress.save() {
+            eprintln!("Error saving the campaign progress: {e}");
+        }
+    }
+
+    /// Starts a local match, the second player guides the shadows with the keyboard
+    pub fn start_versus(&mut self) {
+        self.sim.start_versus();
+        self.start_game();
+    }
+
+    /// Survival mode without win, it lasts until the light is gone
+    pub fn start_endless(&mut self) {
+        self.sim.start_endless();
+        self.start_game();
+    }
+
+    pub fn start_playback(&mut self, replay: Replay) -> Result<(), String> {
+        let config = self.preset(&replay.difficulty)?;
+        let map = self.map_def(&replay.map)?;
+        self.set_tick_rate(replay.tick_rate);
+        self.res.set_background(map.background);
+        self.sim = Simulation::new(replay.seed, config, map);
+        if replay.versus {
+            self.sim.start_versus();
+        }
+        if replay.endless {
+            self.sim.start_endless();
+        }
+        if let Some(id) = &replay.level {
+            let def = self.select_level_def(id)?;
+            self.sim.start_level(def);
+        }
+        if let Some(day) = replay.daily {
+            self.sim.start_daily(Daily::new(day));
+        }
+        self.sim.init_spawn();
+        self.clear_pending_inputs();
+        self.recording = None;
+        self.playback = Some(Playback::new(replay));
+        self.mode = Mode::Playing;
+        Ok(())
+    }
+
+    pub fn is_playback(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    /// Blessings are bought at the beginning of the next tick to keep the runs reproducible
+    pub fn queue_unlock(&mut self, b: Blessing) {
+        if !self.is_playback() {
+            self.pending_unlocks.insert(0, b);
+        }
+    }
+
+    /// Same as unlocks, refunds are applied at the beginning of the next tick
+    pub fn queue_respec(&mut self, b: Blessing) {
+        if !self.is_playback() {
+            self.pending_respecs.insert(0, b);
+        }
+    }
+
+    pub fn queue_ability(&mut self, a: Ability) {
+        if !self.is_playback() {
+            self.pending_abilities.insert(0, a);
+        }
+    }
+
+    fn update_rival_selection(&mut self) {
+        let len = Blessing::all().count();
+        if is_key_pressed(KeyCode::Comma) {
+            self.rival_selected = (self.rival_selected + len - 1) % len;
+        } else if is_key_pressed(KeyCode::Period) {
+            self.rival_selected = (self.rival_selected + 1) % len;
+        }
+
+        if is_key_pressed(KeyCode::ShiftRight) && !self.is_playback() {
+            if let Some(b) = Blessing::all().nth(self.rival_selected) {
+                self.pending_rival_unlocks.insert(0, b);
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_game(&self) -> Result<(), String> {
+        if self.sim.is_versus() {
+            return Err("Versus matches can't be saved".to_string());
+        }
+
+        SaveData::from_sim(&self.sim).save()
     }
 
-    fn camera_movement(&mut self, dt: f32) {
-        if !matches!(self.mode, Mode::Playing) {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_game(&mut self) -> Result<(), String> {
+        let data = SaveData::load()?;
+        let config = self.preset(&data.difficulty)?;
+        let map = self.map_def(&data.map)?;
+        let level = data
+            .level
+            .as_ref()
+            .map(|id| self.select_level_def(id))
+            .transpose()?;
+        self.res.set_background(map.background);
+        self.sim = data.into_sim(config, map, level);
+        self.is_daily_best = false;
+        self.clear_pending_inputs();
+        // a resumed run can't be reproduced from its seed
+        self.recording = None;
+        self.playback = None;
+        self.mode = Mode::Playing;
+        Ok(())
+    }
+
+    fn save_recording(&mut self) {
+        let Some(mut replay) = self.recording.take() else {
             return;
+        };
+
+        replay.finish(&self.sim);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Err(e) = replay.save(ld56::replay::LAST_REPLAY_FILE) {
+            eprintln!("Error saving replay: {e}");
         }
+    }
+
+    pub fn apply_camera(&self, draw: &mut Draw2D) {
+        draw.set_camera(&self.camera);
+    }
 
-        let mut mul = Vec2::ZERO;
+    fn read_input(&self) -> Input {
+        let mut camera_move = Vec2::ZERO;
         if is_moving_left() {
-            mul.x = -1.0;
+            camera_move.x = -1.0;
         } else if is_moving_right() {
-            mul.x = 1.0;
+            camera_move.x = 1.0;
         }
 
         if is_moving_up() {
-            mul.y = -1.0;
+            camera_move.y = -1.0;
         } else if is_moving_down() {
-            mul.y = 1.0;
+            camera_move.y = 1.0;
+        }
+
+        Input {
+            // rounded to keep replays small, it doesn't make any difference to the player
+            guide_pos: self.mouse_pos.round(),
+            is_guiding: is_guiding_souls(),
+            camera_move,
+            unlock: None,
+            respec: None,
+            ability: None,
+            rival: self.read_rival_input(),
+        }
+    }
+
+    fn read_rival_input(&self) -> RivalInput {
+        if !self.sim.is_versus() {
+            return RivalInput::default();
+        }
+
+        let mut move_dir = Vec2::ZERO;
+        if is_key_down(KeyCode::ArrowLeft) {
+            move_dir.x = -1.0;
+        } else if is_key_down(KeyCode::ArrowRight) {
+            move_dir.x = 1.0;
+        }
+
+        if is_key_down(KeyCode::ArrowUp) {
+            move_dir.y = -1.0;
+        } else if is_key_down(KeyCode::ArrowDown) {
+            move_dir.y = 1.0;
+        }
+
+        RivalInput {
+            move_dir,
+            is_guiding: is_key_down(KeyCode::ControlRight),
+            unlock: None,
         }
+    }
+
+    fn update_camera(&mut self) {
+        self.camera.set_size(window_size().floor());
+        let position = self.sim.prev_position.lerp(self.sim.position, self.alpha);
+        self.camera.set_position(position.floor());
+
+        // FIXME not working on the engine, delta is always a positive number using wheel (works with trackpad)
+        // let w_dt = mouse_wheel_delta().y;
+        // if !w_dt.is_zero() {
+        //     println!("{w_dt}");
+        //     let zoom = (self.camera.zoom() + w_dt);
+        //     self.camera.set_zoom(zoom);
+        // }
 
-        self.position += CAMERA_SPEED * mul * dt;
+        self.camera.update();
+        self.mouse_pos = self.camera.screen_to_local(mouse_position());
     }
 }
 
@@ -299,137 +640,17 @@ fn is_moving_down() -> bool {
     is_key_down(KeyCode::KeyS)
 }
 
-fn is_guiding_souls() -> bool {
-    is_mouse_btn_down(MouseButton::Left)
-}
-
-fn radial_random_pos(radius: f32) -> Vec2 {
-    let angle = random::range(0.0..TAU);
-    let r = random::range(0.0..1.0f32).sqrt() * radius;
-    vec2(r * angle.cos(), r * angle.sin())
-}
-
-pub fn move_towards(from: Vec2, to: Vec2, speed: f32) -> Vec2 {
-    let direction = (to - from).normalize_or_zero();
-    let movement = direction * speed;
-    from + movement
-}
-
-pub fn is_close(entity_pos: Vec2, p2: Vec2, radius: f32) -> bool {
-    let dist = entity_pos.distance_squared(p2);
-    let r = radius * radius;
-    dist <= r
-}
-
-fn avoid_overlap(souls: &mut [Soul], min_distance: f32, aabb_index: &StaticAABB2DIndex<f32>) {
-    const REGULAR_FORCE_MULT: f32 = 0.5;
-    const FOLLOWING_FORCE_MUL: f32 = 1.0;
-
-    for i in 0..souls.len() {
-        let p1 = souls[i].pos;
-        let min = p1 - min_distance;
-        // TODO this size is hardcoded and don't check anchors...
-        let max = p1 + 16.0 + min_distance;
-        let close_souls = aabb_index.query(min.x, min.y, max.x, max.y);
-        for n in close_souls {
-            if i == n {
-                continue;
-            }
-
-            let p2 = souls[n].pos;
-            let distance = p1.distance(p2);
-            if distance < min_distance {
-                let overlap = min_distance - distance;
-                let direction = (p1 - p2).normalize_or_zero();
-                let is_following = souls[i].is_following || souls[n].is_following;
-                let force_mul = if is_following {
-                    FOLLOWING_FORCE_MUL
-                } else {
-                    REGULAR_FORCE_MULT
-                };
+/// Abilities are bound to the number keys in the same order
+pub const ABILITY_KEYS: [KeyCode; Ability::ALL.len()] =
+    [KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3];
 
-                souls[i].pos += direction * (overlap * force_mul);
-                souls[n].pos -= direction * (overlap * force_mul);
-            }
-        }
-    }
+fn ability_pressed() -> Option<Ability> {
+    Ability::ALL
+        .into_iter()
+        .zip(ABILITY_KEYS)
+        .find_map(|(a, k)| is_key_pressed(k).then_some(a))
 }
 
-pub fn update_karma(
-    souls: &mut [Soul],
-    dt: f32,
-    radius: f32,
-    rate: f32,
-    expire_rate: f32,
-    use_eternals: bool,
-    aabb_index: &StaticAABB2DIndex<f32>,
-) {
-    let radius_squared = radius * radius;
-
-    // Store karma changes to apply later
-    let karma_updates = souls
-        .iter()
-        .enumerate()
-        .map(|(i, soul)| {
-            let mut conversion = KarmaConversion::Neutral;
-
-            // Eternals are immune to corruption
-            if matches!(soul.kind(), SoulKind::Eternal) {
-                return (i, soul.karma, conversion);
-            }
-
-            let max_karma = if use_eternals { 6.0 } else { 2.0 };
-
-            let pos = soul.pos;
-            let min = pos - radius;
-            let max = pos + radius;
-            let close_souls = aabb_index.query(min.x, min.y, max.x, max.y);
-
-            let mut good_souls = 0;
-            let mut bad_souls = 0;
-
-            // Count good and bad souls in the radius
-            for n in close_souls {
-                if n == i {
-                    continue;
-                }
-
-                let other_soul = &souls[n];
-                if other_soul.pos.distance_squared(pos) <= radius_squared {
-                    match other_soul.kind() {
-                        SoulKind::Shadow => bad_souls += 1,
-                        SoulKind::Luminal if other_soul.is_following => good_souls += 1,
-                        SoulKind::Eternal => good_souls += 1,
-                        _ => {}
-                    }
-                }
-            }
-
-            let expiration = expire_rate * dt;
-            let mut karma = if soul.is_following {
-                soul.karma + expiration
-            } else {
-                soul.karma - expiration
-            };
-
-            if bad_souls > good_souls {
-                let new_rate = rate * 0.2;
-                karma = (karma - new_rate * dt).max(-2.0);
-                conversion = KarmaConversion::Bad;
-            } else if good_souls > bad_souls {
-                let extra = (good_souls as f32 / 50.0).clamp(0.0, 1.0);
-                let new_rate = rate + extra;
-                karma = (karma + new_rate * dt).min(max_karma);
-                conversion = KarmaConversion::Good;
-            }
-
-            (i, karma.clamp(-2.0, max_karma), conversion)
-        })
-        .collect::<Vec<_>>();
-
-    // update the karma
-    for (i, new_karma, conversion) in karma_updates {
-        souls[i].karma = new_karma;
-        souls[i].conversion = conversion;
-    }
+fn is_guiding_souls() -> bool {
+    is_mouse_btn_down(MouseButton::Left)
 }
@@ -0,0 +1,80 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+
+const APP_DIR: &str = "karma-keepers";
+
+/// Per-user directory where the game stores saves and replays
+#[cfg(not(target_arch = "wasm32"))]
+pub fn data_dir() -> Result<PathBuf, String> {
+    let base = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+    };
+
+    let dir = base
+        .ok_or_else(|| "Unable to find the user data directory".to_string())?
+        .join(APP_DIR);
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write(file: &str, content: &str) -> Result<(), String> {
+    let path = data_dir()?.join(file);
+    std::fs::write(path, content).map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read(file: &str) -> Result<String, String> {
+    let path = data_dir()?.join(file);
+    std::fs::read_to_string(path).map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn exists(file: &str) -> bool {
+    data_dir().is_ok_and(|dir| dir.join(file).exists())
+}
+
+// the browser has no data directory, the files are kept in the local storage of the page
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Result<web_sys::Storage, String> {
+    web_sys::window()
+        .ok_or_else(|| "Unable to find the browser window".to_string())?
+        .local_storage()
+        .ok()
+        .flatten()
+        .ok_or_else(|| "The local storage is not available".to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn storage_key(file: &str) -> String {
+    format!("{APP_DIR}/{file}")
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn write(file: &str, content: &str) -> Result<(), String> {
+    local_storage()?
+        .set_item(&storage_key(file), content)
+        .map_err(|_| format!("Unable to write '{file}' to the local storage"))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn read(file: &str) -> Result<String, String> {
+    local_storage()?
+        .get_item(&storage_key(file))
+        .ok()
+        .flatten()
+        .ok_or_else(|| format!("Unable to read '{file}' from the local storage"))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn exists(file: &str) -> bool {
+    local_storage().is_ok_and(|s| s.get_item(&storage_key(file)).is_ok_and(|v| v.is_some()))
+}
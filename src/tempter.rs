@@ -0,0 +1,123 @@
+use crate::rng::Rng;
+use crate::sim::{is_close, move_towards};
+use crate::souls::Soul;
+use crate::terrain::{Terrain, SOUL_SIZE};
+use glam::Vec2;
+use serde::Deserialize;
+
+// neutral souls checked on each decision, enough to find a good cluster without checking all of them
+const CANDIDATES: usize = 16;
+// how much the tempter wants to stay away from the player's circle
+const AVOID_PENALTY: f32 = 10.0;
+
+/// Settings of the shadow agent, each difficulty preset has its own
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct TempterConfig {
+    pub speed: f32,
+    pub radius: f32,
+    /// Speed of the shadows following the tempter
+    pub following_speed: f32,
+    /// Seconds between choosing a new target
+    pub decision_time: f32,
+}
+
+/// Opponent that guides the Shadow souls towards the neutral ones
+#[derive(Clone, Debug)]
+pub struct Tempter {
+    pub config: TempterConfig,
+    pub pos: Vec2,
+    pub prev_pos: Vec2,
+    pub target: Vec2,
+    pub decision_timer: f32,
+}
+
+impl Tempter {
+    pub fn new(config: TempterConfig, pos: Vec2) -> Self {
+        Self {
+            config,
+            pos,
+            prev_pos: pos,
+            target: pos,
+            decision_timer: 0.0,
+        }
+    }
+
+    pub fn interpolated_pos(&self, alpha: f32) -> Vec2 {
+        self.prev_pos.lerp(self.pos, alpha)
+    }
+
+    /// Player's circle as (position, radius), only when the player is guiding
+    pub fn update(
+        &mut self,
+        dt: f32,
+        souls: &[Soul],
+        player: Option<(Vec2, f32)>,
+        terrain: &Terrain,
+        rng: &mut Rng,
+    ) {
+        self.prev_pos = self.pos;
+
+        // the tempter collides like a soul, but its position is the center
+        let half = Vec2::splat(SOUL_SIZE * 0.5);
+
+        // look for another target if something got in the way
+        self.decision_timer -= dt;
+        if self.decision_timer <= 0.0 || !terrain.is_clear(self.pos - half, self.target - half) {
+            self.decision_timer = self.config.decision_time;
+            if let Some(target) = self.choose_target(souls, player, terrain, rng) {
+                self.target = target;
+            }
+        }
+
+        // run away if the player gets too close
+        let mut target = self.target;
+        if let Some((pos, radius)) = player {
+            if is_close(self.pos, pos, radius + self.config.radius) {
+                let away = (self.pos - pos).normalize_or(Vec2::X);
+                target = self.pos + away * self.config.radius;
+            }
+        }
+
+        let speed = self.config.speed * dt;
+        if !is_close(self.pos, target, speed) {
+            self.pos = move_towards(self.pos, target, speed);
+        }
+        self.pos = (terrain.resolve(self.pos - half) + half).clamp(Vec2::ZERO, terrain.map_size);
+    }
+
+    fn choose_target(
+        &self,
+        souls: &[Soul],
+        player: Option<(Vec2, f32)>,
+        terrain: &Terrain,
+        rng: &mut Rng,
+    ) -> Option<Vec2> {
+        let neutrals = souls.iter().filter(|s| s.is_neutral()).collect::<Vec<_>>();
+        if neutrals.is_empty() {
+            return None;
+        }
+
+        let score = |pos: Vec2| {
+            let cluster = neutrals
+                .iter()
+                .filter(|s| is_close(s.pos, pos, self.config.radius))
+                .count() as f32;
+
+            let danger = match player {
+                Some((p, radius)) if is_close(pos, p, radius * 2.0) => AVOID_PENALTY,
+                _ => 0.0,
+            };
+
+            cluster - danger
+        };
+
+        // only the ones that can be reached in a straight line, to not get stuck behind obstacles
+        let half = Vec2::splat(SOUL_SIZE * 0.5);
+        (0..CANDIDATES.min(neutrals.len()))
+            .map(|_| neutrals[rng.index(neutrals.len())].pos)
+            .filter(|pos| terrain.is_clear(self.pos - half, *pos - half))
+            .map(|pos| (pos, score(pos)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(pos, _)| pos)
+    }
+}
@@ -0,0 +1,261 @@
+use crate::rng::Rng;
+use glam::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Size of the navigation grid cells
+pub const TILE_SIZE: f32 = 20.0;
+/// Souls are 16x16 and their position is the top-left corner
+pub const SOUL_SIZE: f32 = 16.0;
+// tries to find a free destination before giving up
+const DESTINATION_TRIES: usize = 8;
+// souls pushed against an obstacle can end up a fraction of a pixel inside of it
+const CONTACT_MARGIN: f32 = 0.5;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ObstacleKind {
+    Rock,
+    River,
+    Wall,
+}
+
+/// Rectangle of the map that souls can't pass through
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Obstacle {
+    pub kind: ObstacleKind,
+    pub pos: (f32, f32),
+    pub size: (f32, f32),
+}
+
+impl Obstacle {
+    pub fn min(&self) -> Vec2 {
+        vec2(self.pos.0, self.pos.1)
+    }
+
+    pub fn max(&self) -> Vec2 {
+        self.min() + vec2(self.size.0, self.size.1)
+    }
+}
+
+/// Static obstacles of the map and a navigation grid to route around them
+pub struct Terrain {
+    pub map_size: Vec2,
+    pub obstacles: Vec<Obstacle>,
+    cols: usize,
+    rows: usize,
+    blocked: Vec<bool>,
+    // cell where the flow field leads to and the next cell to go from each cell
+    flow_target: Option<usize>,
+    flow: Vec<Option<usize>>,
+}
+
+impl Terrain {
+    pub fn new(map_size: Vec2, obstacles: &[Obstacle]) -> Self {
+        let cols = (map_size.x / TILE_SIZE).ceil() as usize;
+        let rows = (map_size.y / TILE_SIZE).ceil() as usize;
+
+        // a cell is blocked if a soul centered on it would touch an obstacle
+        let half = Vec2::splat(SOUL_SIZE * 0.5);
+        let blocked = (0..cols * rows)
+            .map(|i| {
+                let center = cell_center(i, cols);
+                obstacles
+                    .iter()
+                    .any(|o| point_in_box(center, o.min() - half, o.max() + half))
+            })
+            .collect();
+
+        Self {
+            map_size,
+            obstacles: obstacles.to_vec(),
+            cols,
+            rows,
+            blocked,
+            flow_target: None,
+            flow: vec![],
+        }
+    }
+
+    pub fn is_free(&self, pos: Vec2) -> bool {
+        !self.obstacles.iter().any(|o| {
+            let min = o.min() - SOUL_SIZE;
+            point_in_box(pos, min, o.max())
+        })
+    }
+
+    /// Returns true if a soul can go in a straight line between both positions,
+    /// touching an obstacle doesn't block the way
+    pub fn is_clear(&self, from: Vec2, to: Vec2) -> bool {
+        let half = Vec2::splat(SOUL_SIZE * 0.5);
+        let (a, b) = (from + half, to + half);
+        let extent = half - CONTACT_MARGIN;
+        !self
+            .obstacles
+            .iter()
+            .any(|o| segment_hits_box(a, b, o.min() - extent, o.max() + extent))
+    }
+
+    /// Pushes the soul out of any obstacle it's overlapping
+    pub fn resolve(&self, mut pos: Vec2) -> Vec2 {
+        for o in &self.obstacles {
+            let (min, max) = (o.min(), o.max());
+            let overlap_x = (pos.x + SOUL_SIZE).min(max.x) - pos.x.max(min.x);
+            let overlap_y = (pos.y + SOUL_SIZE).min(max.y) - pos.y.max(min.y);
+            if overlap_x <= 0.0 || overlap_y <= 0.0 {
+                continue;
+            }
+
+            // move it out by the shortest side, unless that side is against the edge of the map
+            let pushes = [
+                vec2(min.x - SOUL_SIZE - pos.x, 0.0),
+                vec2(max.x - pos.x, 0.0),
+                vec2(0.0, min.y - SOUL_SIZE - pos.y),
+                vec2(0.0, max.y - pos.y),
+            ];
+            let inside_map = |p: Vec2| p.cmpge(Vec2::ZERO).all() && p.cmple(self.map_size).all();
+            let by_length = |a: &Vec2, b: &Vec2| a.length_squared().total_cmp(&b.length_squared());
+            let push = pushes
+                .into_iter()
+                .filter(|push| inside_map(pos + *push))
+                .min_by(by_length)
+                .or_else(|| pushes.into_iter().min_by(by_length));
+            pos += push.unwrap_or_default();
+        }
+
+        pos
+    }
+
+    /// Random position free of obstacles and reachable in a straight line from `from`,
+    /// or `from` itself if none is found, so the caller can try again later
+    pub fn random_destination(&self, from: Vec2, rng: &mut Rng) -> Vec2 {
+        (0..DESTINATION_TRIES)
+            .map(|_| {
+                vec2(
+                    rng.range(0.0..self.map_size.x),
+                    rng.range(0.0..self.map_size.y),
+                )
+                .round()
+            })
+            .find(|pos| self.is_free(*pos) && self.is_clear(from, *pos))
+            .unwrap_or(from)
+    }
+
+    /// Calculates the paths to the target from the whole map, if it's not done already
+    pub fn update_flow(&mut self, target: Vec2) {
+        let Some(target) = self.cell_at(target) else {
+            return;
+        };
+
+        if self.flow_target == Some(target) {
+            return;
+        }
+
+        let mut flow = vec![None; self.cols * self.rows];
+        let mut visited = vec![false; self.cols * self.rows];
+        visited[target] = true;
+
+        let mut queue = VecDeque::from([target]);
+        while let Some(cell) = queue.pop_front() {
+            for next in self.neighbours(cell) {
+                if !visited[next] {
+                    visited[next] = true;
+                    flow[next] = Some(cell);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        self.flow_target = Some(target);
+        self.flow = flow;
+    }
+
+    /// Direction to follow to reach `to`, going around the obstacles when there is no clear path
+    pub fn direction(&self, from: Vec2, to: Vec2) -> Vec2 {
+        let straight = (to - from).normalize_or_zero();
+        if self.obstacles.is_empty() || self.is_clear(from, to) {
+            return straight;
+        }
+
+        // the flow field is only valid for the last target
+        if self.flow_target != self.cell_at(to) {
+            return straight;
+        }
+
+        let center = from + SOUL_SIZE * 0.5;
+        self.cell_at(center)
+            .and_then(|cell| self.flow.get(cell).copied().flatten())
+            .map(|next| (cell_center(next, self.cols) - center).normalize_or_zero())
+            .unwrap_or(straight)
+    }
+
+    fn cell_at(&self, pos: Vec2) -> Option<usize> {
+        let (x, y) = ((pos.x / TILE_SIZE).floor(), (pos.y / TILE_SIZE).floor());
+        let valid = x >= 0.0 && y >= 0.0 && (x as usize) < self.cols && (y as usize) < self.rows;
+        valid.then(|| y as usize * self.cols + x as usize)
+    }
+
+    fn neighbours(&self, cell: usize) -> impl Iterator<Item = usize> + '_ {
+        let (x, y) = ((cell % self.cols) as i32, (cell / self.cols) as i32);
+        let free = move |x: i32, y: i32| {
+            if x < 0 || y < 0 || x as usize >= self.cols || y as usize >= self.rows {
+                return None;
+            }
+
+            let idx = y as usize * self.cols + x as usize;
+            (!self.blocked[idx]).then_some(idx)
+        };
+
+        [
+            (-1, 0),
+            (1, 0),
+            (0, -1),
+            (0, 1),
+            (-1, -1),
+            (1, -1),
+            (-1, 1),
+            (1, 1),
+        ]
+        .into_iter()
+        .filter_map(move |(dx, dy)| {
+            // diagonals can't cut the corners of the obstacles
+            let is_diagonal = dx != 0 && dy != 0;
+            if is_diagonal && (free(x + dx, y).is_none() || free(x, y + dy).is_none()) {
+                return None;
+            }
+
+            free(x + dx, y + dy)
+        })
+    }
+}
+
+fn cell_center(cell: usize, cols: usize) -> Vec2 {
+    let (x, y) = ((cell % cols) as f32, (cell / cols) as f32);
+    (vec2(x, y) + 0.5) * TILE_SIZE
+}
+
+fn point_in_box(p: Vec2, min: Vec2, max: Vec2) -> bool {
+    p.x > min.x && p.y > min.y && p.x < max.x && p.y < max.y
+}
+
+fn segment_hits_box(a: Vec2, b: Vec2, min: Vec2, max: Vec2) -> bool {
+    let dir = b - a;
+    let mut t_min = 0.0_f32;
+    let mut t_max = 1.0_f32;
+    for (start, d, lo, hi) in [(a.x, dir.x, min.x, max.x), (a.y, dir.y, min.y, max.y)] {
+        if d.abs() < f32::EPSILON {
+            if start <= lo || start >= hi {
+                return false;
+            }
+            continue;
+        }
+
+        let (t1, t2) = ((lo - start) / d, (hi - start) / d);
+        t_min = t_min.max(t1.min(t2));
+        t_max = t_max.min(t1.max(t2));
+        if t_min >= t_max {
+            return false;
+        }
+    }
+
+    true
+}
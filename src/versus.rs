@@ -0,0 +1,86 @@
+use crate::params::{Blessing, Blessings, Params, Prerequisite};
+use glam::Vec2;
+
+// the rival moves with the keyboard, so it needs to be a bit faster than the souls
+const RIVAL_SPEED: f32 = 180.0;
+
+/// Input of the second player for a single simulation step
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RivalInput {
+    pub move_dir: Vec2,
+    pub is_guiding: bool,
+    pub unlock: Option<Blessing>,
+}
+
+/// Second player guiding the Shadow souls in versus mode,
+/// with its own energy and blessings
+pub struct Rival {
+    pub pos: Vec2,
+    pub prev_pos: Vec2,
+    pub is_guiding: bool,
+
+    pub blessings: Blessings,
+    pub params: Params,
+
+    pub energy: u64,
+    pub total_energy: u64,
+}
+
+impl Rival {
+    pub fn new(pos: Vec2, start: &Params, end: &Params) -> Self {
+        let blessings = Blessings::new();
+        let params = blessings.params(start, end);
+        Self {
+            pos,
+            prev_pos: pos,
+            is_guiding: false,
+            blessings,
+            params,
+            energy: 0,
+            total_energy: 0,
+        }
+    }
+
+    pub fn interpolated_pos(&self, alpha: f32) -> Vec2 {
+        self.prev_pos.lerp(self.pos, alpha)
+    }
+
+    pub fn update(&mut self, dt: f32, input: &RivalInput, map_size: Vec2) {
+        self.prev_pos = self.pos;
+        self.pos += input.move_dir.normalize_or_zero() * RIVAL_SPEED * dt;
+        self.pos = self.pos.clamp(Vec2::ZERO, map_size);
+        self.is_guiding = input.is_guiding;
+    }
+
+    /// Only the blessings that change how the shadows are guided
+    pub fn can_buy(&self, b: Blessing) -> bool {
+        let lvl = self.blessings.level(&b);
+        b.is_for_rival()
+            && b.require().iter().all(|p| self.meets(p))
+            && b.exclusive().iter().all(|e| self.blessings.level(e) == 0)
+            && lvl < b.levels()
+            && self.energy >= b.price(lvl)
+    }
+
+    /// Requirements on blessings the rival can't buy are skipped,
+    /// otherwise the blessings after them could never be reached
+    fn meets(&self, p: &Prerequisite) -> bool {
+        match p {
+            Prerequisite::Level(b, lvl) => !b.is_for_rival() || self.blessings.level(b) >= *lvl,
+            Prerequisite::AnyOf(list) => list.iter().any(|p| self.meets(p)),
+            Prerequisite::AllOf(list) => list.iter().all(|p| self.meets(p)),
+        }
+    }
+
+    pub fn unlock_blessing(&mut self, b: Blessing, start: &Params, end: &Params) -> bool {
+        if !self.can_buy(b) {
+            return false;
+        }
+
+        let lvl = self.blessings.level(&b);
+        self.energy -= b.price(lvl);
+        self.blessings.set_level(b, lvl + 1);
+        self.params = self.blessings.params(start, end);
+        true
+    }
+}
@@ -0,0 +1,296 @@
+use crate::endless::EndlessConfig;
+use crate::rng::Rng;
+use crate::sim::radial_random_pos;
+use crate::souls::SoulKind;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+// souls of each group when a wave comes in clusters
+const CLUSTER_SIZE: usize = 5;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Edge {
+    North,
+    South,
+    East,
+    West,
+}
+
+/// Where the souls of a wave appear
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum WaveLocation {
+    /// Middle of the map
+    Center,
+    /// Any of the spawn points of the map, or the center if there are none
+    #[default]
+    Random,
+    /// Spawn point of the map with this name
+    Point(String),
+    /// Along one of the sides of the map
+    Edge(Edge),
+}
+
+/// How the souls of a wave are placed in its location
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Formation {
+    /// Spread in a circle
+    #[default]
+    Disk,
+    /// In a row, along the edge for the edge locations
+    Line,
+    /// Small groups close to each other
+    Clusters,
+}
+
+/// Wave of the map that comes at a fixed time
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WaveDef {
+    /// Seconds since the beginning of the game
+    pub time: f32,
+    /// Announced before the wave comes, a generic text is used if it's not set
+    #[serde(default)]
+    pub name: Option<String>,
+    pub count: usize,
+    /// Weight of each kind of soul in the wave
+    #[serde(default = "default_kinds")]
+    pub kinds: Vec<(SoulKind, f32)>,
+    #[serde(default)]
+    pub location: WaveLocation,
+    #[serde(default)]
+    pub formation: Formation,
+    /// Chance of each soul to be a wanderer, the preset's one if it's not set
+    #[serde(default)]
+    pub wanderers: Option<f32>,
+}
+
+fn default_kinds() -> Vec<(SoulKind, f32)> {
+    vec![(SoulKind::Neutral, 1.0)]
+}
+
+impl WaveDef {
+    /// Picks the kind of a soul using the weights of the wave plus the bias of the area
+    pub fn random_kind(&self, bias: &[(SoulKind, f32)], rng: &mut Rng) -> SoulKind {
+        let kinds = self.kinds.iter().chain(bias);
+        let total = kinds.clone().map(|(_, w)| w).sum::<f32>();
+        let mut value = rng.range(0.0..total);
+        for (kind, weight) in kinds {
+            if value < *weight {
+                return *kind;
+            }
+            value -= weight;
+        }
+
+        self.kinds.last().map_or(SoulKind::Neutral, |(k, _)| *k)
+    }
+}
+
+/// Place where the souls of a wave appear
+#[derive(Clone, Debug)]
+pub struct SpawnArea {
+    pub center: Vec2,
+    pub radius: f32,
+    /// Direction of the lines
+    pub axis: Vec2,
+    /// Extra weight of each kind of soul, from the spawn point
+    pub bias: Vec<(SoulKind, f32)>,
+}
+
+/// Regular waves that keep coming during the whole game, each difficulty preset has its own
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WaveConfig {
+    /// Seconds that the time between waves is reduced after each one
+    pub delay_step: f32,
+    pub min_delay: f32,
+    /// Souls of the biggest wave, each wave brings one more than the last one
+    pub max_count: usize,
+    /// Seconds before a wave comes that it's announced
+    pub announce_time: f32,
+}
+
+impl Default for WaveConfig {
+    fn default() -> Self {
+        Self {
+            delay_step: 0.5,
+            min_delay: 5.0,
+            max_count: 20,
+            announce_time: 5.0,
+        }
+    }
+}
+
+/// Text shown to the player before a wave comes
+#[derive(Clone, Debug)]
+pub struct Announcement {
+    pub text: String,
+    pub time_left: f32,
+    /// Scripted waves of the map are more important than the regular ones
+    pub is_special: bool,
+}
+
+/// Spawner with the regular waves and the scripted ones of the map
+#[derive(Clone, Debug)]
+pub struct Waves {
+    pub config: WaveConfig,
+    // sorted by time
+    pub scripted: Vec<WaveDef>,
+    pub next_scripted: usize,
+
+    // regular waves
+    pub delay: f32,
+    pub timer: f32,
+    pub count: usize,
+    /// Number of waves that came so far
+    pub number: usize,
+    /// The regular waves don't stop growing in endless mode
+    pub endless: Option<EndlessConfig>,
+}
+
+impl Waves {
+    pub fn new(config: WaveConfig, scripted: &[WaveDef], delay: f32) -> Self {
+        let mut scripted = scripted.to_vec();
+        scripted.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self {
+            config,
+            scripted,
+            next_scripted: 0,
+            delay,
+            timer: delay,
+            count: 1,
+            number: 0,
+            endless: None,
+        }
+    }
+
+    /// Souls of the next regular wave, the blessings can block some of them
+    pub fn next_count(&self, blocked: usize) -> usize {
+        (self.count + 1)
+            .min(self.max_count())
+            .checked_sub(blocked)
+            .unwrap_or(1)
+    }
+
+    fn max_count(&self) -> usize {
+        self.endless.map_or(self.config.max_count, |e| e.max_count)
+    }
+
+    /// More and more Shadows come with the regular waves in endless mode
+    fn regular_kinds(&self, play_time: f32) -> Vec<(SoulKind, f32)> {
+        match self.endless {
+            Some(e) => vec![
+                (SoulKind::Neutral, 1.0),
+                (SoulKind::Shadow, e.shadow_growth * play_time / 60.0),
+            ],
+            None => default_kinds(),
+        }
+    }
+
+    /// Scripted waves already gone are skipped, used when a game is resumed
+    pub fn skip_to(&mut self, play_time: f32) {
+        self.next_scripted = self.scripted.iter().filter(|w| w.time <= play_time).count();
+    }
+
+    /// Waves that come on this tick, `slow_time` and `blocked` are from the blessings
+    /// and they only change the regular waves
+    pub fn update(
+        &mut self,
+        dt: f32,
+        play_time: f32,
+        slow_time: f32,
+        blocked: usize,
+    ) -> Vec<WaveDef> {
+        let mut waves = vec![];
+
+        self.timer -= dt;
+        if self.timer <= 0.0 {
+            let count = self.next_count(blocked);
+            self.delay = (self.delay - self.config.delay_step).max(self.config.min_delay);
+            self.timer = self.delay + slow_time;
+            self.count = (self.count + 1).min(self.max_count());
+            waves.push(WaveDef {
+                time: play_time,
+                name: None,
+                count,
+                kinds: self.regular_kinds(play_time),
+                location: WaveLocation::Random,
+                formation: Formation::Disk,
+                wanderers: None,
+            });
+        }
+
+        while let Some(w) = self.scripted.get(self.next_scripted) {
+            if w.time > play_time {
+                break;
+            }
+
+            waves.push(w.clone());
+            self.next_scripted += 1;
+        }
+
+        self.number += waves.len();
+        waves
+    }
+
+    /// The scripted waves have priority over the regular ones
+    pub fn announcement(&self, play_time: f32, blocked: usize) -> Option<Announcement> {
+        let announce_time = self.config.announce_time;
+        if let Some(w) = self.scripted.get(self.next_scripted) {
+            let time_left = w.time - play_time;
+            if time_left <= announce_time {
+                let text = w
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("{} souls are coming!", w.count));
+                return Some(Announcement {
+                    text,
+                    time_left,
+                    is_special: true,
+                });
+            }
+        }
+
+        (self.timer <= announce_time).then(|| Announcement {
+            text: format!(
+                "Wave {}: {} souls",
+                self.number + 1,
+                self.next_count(blocked)
+            ),
+            time_left: self.timer,
+            is_special: false,
+        })
+    }
+}
+
+/// Positions of the souls of a wave
+pub fn formation_positions(
+    formation: Formation,
+    n: usize,
+    area: &SpawnArea,
+    rng: &mut Rng,
+) -> Vec<Vec2> {
+    let SpawnArea {
+        center,
+        radius,
+        axis,
+        ..
+    } = *area;
+    match formation {
+        Formation::Disk => (0..n)
+            .map(|_| center + radial_random_pos(rng, radius))
+            .collect(),
+        Formation::Line => (0..n)
+            .map(|i| {
+                let t = (i as f32 + 0.5) / n as f32 * 2.0 - 1.0;
+                center + axis * radius * t + radial_random_pos(rng, 6.0)
+            })
+            .collect(),
+        Formation::Clusters => {
+            let groups = (0..n.div_ceil(CLUSTER_SIZE))
+                .map(|_| center + radial_random_pos(rng, radius))
+                .collect::<Vec<_>>();
+            (0..n)
+                .map(|i| groups[i / CLUSTER_SIZE] + radial_random_pos(rng, radius * 0.25))
+                .collect()
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::map::{Zone, ZoneKind};
+use crate::sim::is_close;
+use crate::souls::{Soul, SoulKind};
+use glam::Vec2;
+use serde::Deserialize;
+
+/// Strength of the map zones, each difficulty preset can tune them
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ZoneConfig {
+    /// Karma per second given by the shrines
+    pub shrine_rate: f32,
+    /// Karma per second drained by the corrupted pools and the corrupted shrines
+    pub corruption_rate: f32,
+    /// Seconds that the shadows need to outnumber the light to corrupt a shrine
+    pub capture_time: f32,
+}
+
+impl Default for ZoneConfig {
+    fn default() -> Self {
+        Self {
+            shrine_rate: 0.15,
+            corruption_rate: 0.2,
+            capture_time: 8.0,
+        }
+    }
+}
+
+/// Zone of the map while playing, shrines can be captured by the shadows and purified back
+#[derive(Copy, Clone, Debug)]
+pub struct ActiveZone {
+    pub kind: ZoneKind,
+    pub pos: Vec2,
+    pub radius: f32,
+    /// From 0 (pure) to 1, the shrine turns corrupted when it's full
+    pub corruption: f32,
+    pub is_corrupted: bool,
+}
+
+impl ActiveZone {
+    pub fn contains(&self, pos: Vec2) -> bool {
+        is_close(pos, self.pos, self.radius)
+    }
+
+    /// Corrupted shrines work like a corrupted pool
+    pub fn is_draining(&self) -> bool {
+        match self.kind {
+            ZoneKind::Shrine => self.is_corrupted,
+            ZoneKind::CorruptedPool => true,
+            ZoneKind::Sanctuary => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Zones {
+    pub config: ZoneConfig,
+    pub list: Vec<ActiveZone>,
+}
+
+impl Zones {
+    pub fn new(config: ZoneConfig, zones: &[Zone]) -> Self {
+        let list = zones
+            .iter()
+            .map(|z| ActiveZone {
+                kind: z.kind,
+                pos: z.pos(),
+                radius: z.radius,
+                corruption: 0.0,
+                is_corrupted: false,
+            })
+            .collect();
+
+        Self { config, list }
+    }
+
+    /// Shrines fill up their corruption while the shadows outnumber the light inside them
+    pub fn update(&mut self, dt: f32, souls: &[Soul]) {
+        let step = dt / self.config.capture_time;
+        self.list
+            .iter_mut()
+            .filter(|z| z.kind == ZoneKind::Shrine)
+            .for_each(|z| {
+                let (good, bad) = souls.iter().filter(|s| z.contains(s.pos)).fold(
+                    (0, 0),
+                    |(good, bad), s| match s.kind() {
+                        SoulKind::Luminal | SoulKind::Eternal => (good + 1, bad),
+                        SoulKind::Shadow => (good, bad + 1),
+                        SoulKind::Neutral => (good, bad),
+                    },
+                );
+
+                if bad > good {
+                    z.corruption = (z.corruption + step).min(1.0);
+                } else if good > bad {
+                    z.corruption = (z.corruption - step).max(0.0);
+                }
+
+                // it needs to be fully purified to work as a shrine again
+                if z.corruption >= 1.0 {
+                    z.is_corrupted = true;
+                } else if z.corruption <= 0.0 {
+                    z.is_corrupted = false;
+                }
+            });
+    }
+
+    /// Karma per second at the position and if the conversion is blocked there
+    pub fn karma_modifiers(&self, pos: Vec2) -> (f32, bool) {
+        self.list.iter().filter(|z| z.contains(pos)).fold(
+            (0.0, false),
+            |(rate, blocked), z| match z.kind {
+                ZoneKind::Sanctuary => (rate, true),
+                _ if z.is_draining() => (rate - self.config.corruption_rate, blocked),
+                _ => (rate + self.config.shrine_rate, blocked),
+            },
+        )
+    }
+}